@@ -27,13 +27,41 @@ fn main() {
         .trim()
         .replace("{OS}", OS)
         .replace("{ARCH}", ARCH)
-        .replace("{VERSION}", env!("CARGO_PKG_VERSION"));
+        .replace("{VERSION}", env!("CARGO_PKG_VERSION"))
+        .replace("{CHANNEL}", &nativestart::channel());
+
+    // modes for deployment tooling: prepare or check the installation without starting the application
+    // or show what would be done
+    let install_only = std::env::args().any(|arg| arg == "--nativestart:install");
+    let verify_only = std::env::args().any(|arg| arg == "--nativestart:verify");
+    let dry_run = std::env::args().any(|arg| arg == "--nativestart:dry-run");
+    let checksum = std::env::args().any(|arg| arg == "--nativestart:checksum");
 
     #[cfg(feature = "check-signature")]
-    nativestart::start(application_name, application_descriptor_url, APPLICATION_PUBLIC_KEY);
+    if install_only {
+        std::process::exit(nativestart::install(application_name, application_descriptor_url, APPLICATION_PUBLIC_KEY));
+    } else if verify_only {
+        std::process::exit(nativestart::verify(application_name, application_descriptor_url, APPLICATION_PUBLIC_KEY));
+    } else if dry_run {
+        std::process::exit(nativestart::dry_run(application_name, application_descriptor_url, APPLICATION_PUBLIC_KEY));
+    } else if checksum {
+        std::process::exit(nativestart::checksum(application_name, APPLICATION_PUBLIC_KEY));
+    } else {
+        nativestart::start(application_name, application_descriptor_url, APPLICATION_PUBLIC_KEY);
+    }
 
     #[cfg(not(feature = "check-signature"))]
-    nativestart::start(application_name, application_descriptor_url);
+    if install_only {
+        std::process::exit(nativestart::install(application_name, application_descriptor_url));
+    } else if verify_only {
+        std::process::exit(nativestart::verify(application_name, application_descriptor_url));
+    } else if dry_run {
+        std::process::exit(nativestart::dry_run(application_name, application_descriptor_url));
+    } else if checksum {
+        std::process::exit(nativestart::checksum(application_name));
+    } else {
+        nativestart::start(application_name, application_descriptor_url);
+    }
 }
 
 #[cfg(target_os="windows")]
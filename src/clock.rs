@@ -0,0 +1,108 @@
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(test)]
+use std::sync::Mutex;
+
+/// Source of the current time for time based features (download speed, animations, timeouts), so tests can
+/// advance the time manually instead of waiting
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Wall clock time, e.g. for timestamps stored between launches
+    fn system_time(&self) -> SystemTime;
+
+    fn sleep(&self, duration: Duration);
+
+    fn elapsed(&self, since: Instant) -> Duration {
+        return self.now().saturating_duration_since(since);
+    }
+}
+
+/// Clock of the operating system
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        return Instant::now();
+    }
+
+    fn system_time(&self) -> SystemTime {
+        return SystemTime::now();
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Clock which only advances if requested, sleeping advances it immediately. With a step, every reading
+/// advances the clock as well, e.g. to simulate time passing between progress updates.
+#[cfg(test)]
+pub struct ManualClock {
+    start: Instant,
+    system_start: SystemTime,
+    now: Mutex<Instant>,
+    step: Duration,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        return ManualClock::with_step(Duration::ZERO);
+    }
+
+    pub fn with_step(step: Duration) -> ManualClock {
+        let start = Instant::now();
+        return ManualClock { start, system_start: SystemTime::now(), now: Mutex::new(start), step };
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        let mut now = self.now.lock().unwrap();
+        let current = *now;
+        *now += self.step;
+        return current;
+    }
+
+    fn system_time(&self) -> SystemTime {
+        return self.system_start + self.now.lock().unwrap().duration_since(self.start);
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+    use crate::clock::{Clock, ManualClock};
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        assert_eq!(start, clock.now());
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(Duration::from_millis(500), clock.elapsed(start));
+        clock.sleep(Duration::from_secs(2));
+        assert_eq!(Duration::from_millis(2500), clock.elapsed(start));
+        // earlier points in time do not panic
+        assert_eq!(Duration::ZERO, clock.elapsed(clock.now() + Duration::from_secs(1)));
+
+        let system_time = clock.system_time();
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(Duration::from_secs(60), clock.system_time().duration_since(system_time).unwrap());
+        assert!(clock.system_time() > SystemTime::now());
+
+        let clock = ManualClock::with_step(Duration::from_secs(1));
+        let start = clock.now();
+        assert_eq!(Duration::from_secs(1), clock.elapsed(start));
+    }
+}
@@ -1,27 +1,38 @@
+use std::collections::BTreeMap;
 use std::path::Path;
 use serde_derive::*;
 use log::*;
 use crate::errors::*;
+use crate::installation_manager::combine_checksums;
 
 #[cfg(feature = "check-signature")]
 use ring::signature;
 
+/// newest descriptor schema version supported by this launcher
+pub const SUPPORTED_SCHEMA_VERSION: u32 = 1;
+
 #[derive(Deserialize, Debug)]
+#[cfg_attr(feature = "strict-descriptor", serde(deny_unknown_fields))]
 pub struct ApplicationDescriptor {
+    /// version of the descriptor format, descriptors without version have version 1
+    pub schema_version: Option<u32>,
     pub name: String,
     pub version: String,
     pub signature: Option<String>,
-    pub splash: ApplicationComponent,
+    /// splash screen archive, a built-in splash screen is shown if missing
+    pub splash: Option<ApplicationComponent>,
     #[serde(rename="jvm")]
     pub jvm_params: JvmParameters,
     #[serde(rename="component")]
     pub components: Vec<ApplicationComponent>,
     #[serde(rename="unmanaged")]
-    pub unmanaged_paths: Option<Vec<String>>
+    pub unmanaged_paths: Option<Vec<String>>,
+    /// command and arguments run once after installation or update, the command must be a component
+    pub post_install: Option<Vec<String>>,
 }
 
 impl ApplicationDescriptor {
-    pub fn parse(content: &str, public_key: Option<[u8; 32]>) -> Result<ApplicationDescriptor> {
+    pub fn parse(content: &str, application_name: &str, public_key: Option<[u8; 32]>) -> Result<ApplicationDescriptor> {
         let descriptor: Result<ApplicationDescriptor> = toml::from_str(&content).map_err(|e| {
             error!("Descriptor is invalid:\n{}", content);
             ErrorKind::InvalidDescriptor(e.to_string()).into()
@@ -30,9 +41,73 @@ impl ApplicationDescriptor {
         // check signature if required
         match descriptor {
             Ok(desc) => {
-                for component in &desc.all_components() {
-                    if component.path.contains("..") {
-                        panic!("Descriptor defines storage location outside application directory. Please inform author about this security incident!");
+                let schema_version = desc.schema_version.unwrap_or(1);
+                if schema_version > SUPPORTED_SCHEMA_VERSION {
+                    error!("Descriptor has schema version {}, but only {} is supported", schema_version, SUPPORTED_SCHEMA_VERSION);
+                    return Err(ErrorKind::InvalidDescriptor(format!("The application requires a newer launcher (schema version {}). Please download the latest version of the launcher.", schema_version)).into());
+                }
+
+                // detect wrong descriptor URLs and descriptors of other applications
+                if desc.name != application_name {
+                    error!("Descriptor is for application {}, but {} was expected", desc.name, application_name);
+                    return Err(ErrorKind::InvalidDescriptor(format!("Descriptor is for application {}, but {} was expected", desc.name, application_name)).into());
+                }
+
+                for component in desc.all_components() {
+                    if !component.checksum_algorithm().is_supported() {
+                        return Err(ErrorKind::InvalidDescriptor(format!("Checksum algorithm {:?} of {} is not supported by the launcher", component.checksum_algorithm(), component.path)).into());
+                    }
+                    if component.download_checksum.is_some() && !component.is_archive() {
+                        return Err(ErrorKind::InvalidDescriptor(format!("download_checksum of {} is only supported for archives", component.path)).into());
+                    }
+                }
+
+                let mut paths: Vec<&String> = Vec::new();
+                for component in desc.all_components() {
+                    paths.push(&component.path);
+                    paths.extend(&component.cache_path);
+                }
+                paths.extend(desc.unmanaged_paths.iter().flatten());
+                paths.extend(&desc.jvm_params.working_dir);
+                for path in paths {
+                    if !ApplicationDescriptor::is_relative_inside(path) {
+                        error!("Descriptor defines storage location {} outside application directory", path);
+                        return Err(ErrorKind::SignatureError(format!("Descriptor defines storage location {} outside application directory. Please inform author about this security incident!", path)).into());
+                    }
+                }
+                // files of one component would be replaced or deleted by another one
+                if let Some((path, other_path)) = desc.find_conflicting_paths() {
+                    error!("Descriptor defines overlapping paths {} and {}", path, other_path);
+                    return Err(ErrorKind::InvalidDescriptor(format!("Paths {} and {} overlap, each component needs its own location", path, other_path)).into());
+                }
+                desc.jvm_params.validate_memory_sizes()?;
+                for (name, value) in desc.jvm_params.env.iter().flatten() {
+                    if name.is_empty() || name.contains(['=', '\0']) || value.contains('\0') {
+                        return Err(ErrorKind::InvalidDescriptor(format!("Invalid environment variable {:?}", name)).into());
+                    }
+                }
+                // the JVM library is resolved relative to the JVM path
+                let jvm_library = format!("{}/{}", desc.jvm_params.jvm_path, desc.jvm_params.jvm_library);
+                for path in [&desc.jvm_params.jvm_path, &jvm_library] {
+                    if !ApplicationDescriptor::is_relative_inside(path) {
+                        error!("Descriptor defines JVM location {} outside application directory", path);
+                        return Err(ErrorKind::SignatureError(format!("Descriptor defines JVM location {} outside application directory. Please inform author about this security incident!", path)).into());
+                    }
+                }
+                // an absolute library would replace the JVM path when both are joined
+                let library = &desc.jvm_params.jvm_library;
+                if !ApplicationDescriptor::is_relative_inside(library) || library.split(['/', '\\']).any(str::is_empty) {
+                    error!("Descriptor defines JVM library {} outside JVM directory", library);
+                    return Err(ErrorKind::SignatureError(format!("Descriptor defines JVM library {} outside JVM directory. Please inform author about this security incident!", library)).into());
+                }
+                // only run commands protected by the checksum of a component
+                if let Some(command) = desc.post_install.as_ref().and_then(|command| command.first()) {
+                    if !ApplicationDescriptor::is_relative_inside(command) {
+                        error!("Descriptor defines post-install command {} outside application directory", command);
+                        return Err(ErrorKind::SignatureError(format!("Descriptor defines post-install command {} outside application directory. Please inform author about this security incident!", command)).into());
+                    }
+                    if !desc.is_component(command) {
+                        return Err(ErrorKind::InvalidDescriptor(format!("Post-install command {} is not part of a component", command)).into());
                     }
                 }
                 if public_key.is_some() {
@@ -53,10 +128,81 @@ impl ApplicationDescriptor {
     pub fn all_components(&self) -> Vec<&ApplicationComponent> {
         let mut component = Vec::new();
         component.extend(&self.components);
-        component.push(&self.splash);
+        component.extend(&self.splash);
         return component;
     }
 
+    /// Returns the first two component or cache paths that are equal or nested into each other
+    fn find_conflicting_paths(&self) -> Option<(&String, &String)> {
+        let mut paths: Vec<&String> = Vec::new();
+        for component in self.all_components() {
+            paths.push(&component.path);
+            paths.extend(&component.cache_path);
+        }
+        for (index, path) in paths.iter().enumerate() {
+            let segments = ApplicationDescriptor::path_segments(path);
+            for other_path in &paths[index + 1..] {
+                let other_segments = ApplicationDescriptor::path_segments(other_path);
+                let common = segments.len().min(other_segments.len());
+                if segments[..common] == other_segments[..common] {
+                    return Some((path, other_path));
+                }
+            }
+        }
+        return None;
+    }
+
+    /// Splits a path into its normalized segments, ignoring the separator, `.` and the case (the file systems of
+    /// Windows and macOS are case-insensitive)
+    fn path_segments(path: &str) -> Vec<String> {
+        let mut segments: Vec<String> = Vec::new();
+        for segment in path.split(|c| c == '/' || c == '\\') {
+            match segment {
+                "" | "." => {}
+                ".." => { segments.pop(); }
+                _ => segments.push(segment.to_lowercase())
+            }
+        }
+        return segments;
+    }
+
+    /// Checks if the path is a component or contained in an archive component
+    fn is_component(&self, path: &str) -> bool {
+        return self.components.iter().any(|component| component.path == path
+            || (component.is_archive() && path.starts_with(&format!("{}/", component.path.trim_end_matches('/')))));
+    }
+
+    /// Expected combined checksum of the installation, see `InstallationManager::installation_checksum`
+    pub fn checksum(&self) -> String {
+        let checksums = self.all_components().iter()
+            .map(|component| (component.path.clone(), component.checksum.clone()))
+            .collect();
+        return combine_checksums(&checksums);
+    }
+
+    /// Checks that a path is relative and does not leave the directory it is resolved against
+    fn is_relative_inside(path: &str) -> bool {
+        // check both separators and drive letters independent of the current platform
+        if path.starts_with('/') || path.starts_with('\\') {
+            return false;
+        }
+        let mut depth = 0;
+        for segment in path.split(|c| c == '/' || c == '\\') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    if depth == 0 {
+                        return false;
+                    }
+                    depth -= 1;
+                }
+                _ if segment.contains(':') => return false,
+                _ => depth += 1
+            }
+        }
+        return true;
+    }
+
     #[cfg(not(feature = "check-signature"))]
     fn verify(_content: &str, _signature: &Option<String>, _public_key: [u8; 32]) -> Result<()> {
         // no signature checking available
@@ -92,6 +238,7 @@ impl ApplicationDescriptor {
 }
 
 #[derive(Deserialize, Debug)]
+#[cfg_attr(feature = "strict-descriptor", serde(deny_unknown_fields))]
 pub struct JvmParameters {
     #[serde(rename="path")]
     pub jvm_path: String,
@@ -100,22 +247,188 @@ pub struct JvmParameters {
     #[serde(rename="main")]
     pub main_class: String,
     pub options: Vec<String>,
+    /// maximum heap size like `2g`, converted to `-Xmx`
+    pub max_heap: Option<String>,
+    /// initial heap size like `512m`, converted to `-Xms`
+    pub min_heap: Option<String>,
+    /// thread stack size like `1m`, converted to `-Xss`
+    pub stack_size: Option<String>,
+    /// seconds to wait for awaitUI() before hiding the splash screen anyway
+    pub await_ui_timeout: Option<u64>,
+    /// `false` skips the thread calling awaitUI() and hides the splash screen once the JVM is created
+    pub await_ui: Option<bool>,
+    /// when the splash screen is closed after starting the application, by default once the UI is visible
+    pub splash_close: Option<SplashClosePolicy>,
+    /// expected Java version (e.g. `17` or `17.0.1`), compared with the `java.version` of the loaded JVM
+    pub version: Option<String>,
+    /// restart the application up to this number of times if it terminates abnormally
+    pub max_restarts: Option<u32>,
+    /// working directory of the application relative to the installation directory, defaults to the installation directory
+    pub working_dir: Option<String>,
+    /// environment variables of the application, variables already set in the environment of the launcher take precedence
+    pub env: Option<BTreeMap<String, String>>,
+}
+
+/// When the splash screen is closed once the application has been started
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SplashClosePolicy {
+    /// once awaitUI() returned, immediately if the application does not implement it
+    #[default]
+    OnUiVisible,
+    /// after the given number of milliseconds
+    Timeout(u64),
+    /// once the application calls hideSplash()
+    Manual,
+}
+
+impl JvmParameters {
+    /// smallest heap size accepted, smaller values are most likely a missing unit
+    const MIN_HEAP_SIZE: u64 = 1024 * 1024;
+    /// smallest thread stack size accepted
+    const MIN_STACK_SIZE: u64 = 64 * 1024;
+
+    /// The memory sizes as JVM options followed by the raw options
+    pub fn all_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        for (option, value) in self.memory_sizes() {
+            if let Some((size, _)) = value.and_then(|value| JvmParameters::parse_memory_size(value)) {
+                options.push(format!("{}{}", option, size));
+            }
+        }
+        options.extend(self.options.iter().cloned());
+        return options;
+    }
+
+    fn memory_sizes(&self) -> [(&'static str, Option<&String>); 3] {
+        return [("-Xmx", self.max_heap.as_ref()), ("-Xms", self.min_heap.as_ref()), ("-Xss", self.stack_size.as_ref())];
+    }
+
+    /// Rejects memory sizes which are malformed, too small, contradicting or also given as raw option
+    fn validate_memory_sizes(&self) -> Result<()> {
+        let names = ["max_heap", "min_heap", "stack_size"];
+        let minimums = [JvmParameters::MIN_HEAP_SIZE, JvmParameters::MIN_HEAP_SIZE, JvmParameters::MIN_STACK_SIZE];
+        for (((option, value), name), minimum) in self.memory_sizes().into_iter().zip(names).zip(minimums) {
+            let value = match value {
+                Some(value) => value,
+                None => continue
+            };
+            match JvmParameters::parse_memory_size(value) {
+                Some((_, bytes)) if bytes >= minimum => {},
+                Some(_) => bail!(ErrorKind::InvalidDescriptor(format!("{} {} is too small, the unit may be missing (e.g. 512m)", name, value))),
+                None => bail!(ErrorKind::InvalidDescriptor(format!("Invalid {} {}, expected a size like 512m or 2g", name, value))),
+            }
+            if self.options.iter().any(|raw_option| raw_option.starts_with(option)) {
+                bail!(ErrorKind::InvalidDescriptor(format!("{} is defined twice, by {} and in the options", option, name)));
+            }
+        }
+        let heap_sizes = (self.min_heap.as_deref().and_then(JvmParameters::parse_memory_size),
+                          self.max_heap.as_deref().and_then(JvmParameters::parse_memory_size));
+        if let (Some((min_heap, min_bytes)), Some((max_heap, max_bytes))) = heap_sizes {
+            if min_bytes > max_bytes {
+                bail!(ErrorKind::InvalidDescriptor(format!("min_heap {} is larger than max_heap {}", min_heap, max_heap)));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Parses a size like `512m`, `2G` or `64KB` and returns it in the format of the JVM options (`512m`) and in bytes
+    fn parse_memory_size(value: &str) -> Option<(String, u64)> {
+        let value = value.trim().to_ascii_lowercase();
+        let value = value.strip_suffix('b').filter(|number| number.ends_with(['k', 'm', 'g'])).unwrap_or(&value);
+        let (number, unit, factor) = match value.char_indices().last() {
+            Some((index, 'k')) => (&value[..index], "k", 1u64 << 10),
+            Some((index, 'm')) => (&value[..index], "m", 1u64 << 20),
+            Some((index, 'g')) => (&value[..index], "g", 1u64 << 30),
+            _ => (value, "", 1)
+        };
+        if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let bytes = number.parse::<u64>().ok()?.checked_mul(factor)?;
+        return Some((format!("{}{}", number, unit), bytes));
+    }
 }
 
 #[derive(Deserialize, Debug)]
 #[derive(Clone)]
+#[cfg_attr(feature = "strict-descriptor", serde(deny_unknown_fields))]
 pub struct ApplicationComponent {
     pub url: String,
     pub size: u64,
     pub download_size: Option<u64>,
+    /// checksum of the compressed archive, the archive is verified before it is unpacked if defined
+    pub download_checksum: Option<String>,
     pub checksum: String,
     pub path: String,
     pub cache_path: Option<String>,
+    /// Unix permission bits (e.g. `0o755`), ignored on Windows
+    pub mode: Option<u32>,
+    /// If not defined, components with a path ending with `/` are archives
+    #[serde(rename="type")]
+    pub component_type: Option<ComponentType>,
+    /// Number of leading directories removed from the archive entries, like `tar --strip-components`
+    pub strip_components: Option<usize>,
+    /// Algorithm of the checksum, BLAKE3 if not defined
+    pub checksum_algorithm: Option<ChecksumAlgorithm>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ChecksumAlgorithm {
+    #[default]
+    #[serde(rename="blake3")]
+    Blake3,
+    /// only supported with the feature `sha256`
+    #[serde(rename="sha256")]
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub fn is_supported(&self) -> bool {
+        return match self {
+            ChecksumAlgorithm::Blake3 => true,
+            ChecksumAlgorithm::Sha256 => cfg!(feature = "sha256"),
+        };
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ComponentType {
+    /// zstd compressed tar archive extracted to the directory of the path
+    #[serde(rename="archive")]
+    Archive,
+    #[serde(rename="file")]
+    File,
 }
 
 impl ApplicationComponent {
     pub fn is_archive(&self) -> bool {
-        self.path.ends_with("/")
+        return match self.component_type {
+            Some(component_type) => component_type == ComponentType::Archive,
+            None => self.path.ends_with("/")
+        };
+    }
+
+    pub fn checksum_algorithm(&self) -> ChecksumAlgorithm {
+        return self.checksum_algorithm.unwrap_or_default();
+    }
+
+    /// Component without any optional attributes, tests override the attributes they need
+    #[cfg(test)]
+    pub fn for_test(path: &str, size: u64, checksum: &str) -> ApplicationComponent {
+        return ApplicationComponent {
+            url: String::from("http://host/file"),
+            size,
+            download_size: None,
+            download_checksum: None,
+            checksum: String::from(checksum),
+            path: String::from(path),
+            cache_path: None,
+            mode: None,
+            component_type: None,
+            strip_components: None,
+            checksum_algorithm: None,
+        };
     }
 }
 
@@ -125,17 +438,43 @@ impl AsRef<Path> for ApplicationComponent {
     }
 }
 
+/// Valid descriptor shared by the tests, they replace the part under test.
+/// The checksums match files containing `test`.
+#[cfg(test)]
+pub(crate) const TEST_DESCRIPTOR: &str = r#"
+name = "app"
+version = "1.0"
+
+[splash]
+url = "https://example.com/splash"
+size = 4
+checksum = "4878ca0425c739fa427f7eda20fe845f6b2e46ba5fe2a14df5b1e32f50603215"
+path = "splash"
+
+[jvm]
+path = "jvm/bin"
+library = "server/jvm.dll"
+main = "app/Main"
+options = []
+
+[[component]]
+url = "https://example.com/app.jar"
+size = 4
+checksum = "4878ca0425c739fa427f7eda20fe845f6b2e46ba5fe2a14df5b1e32f50603215"
+path = "lib/app.jar"
+"#;
 
 #[cfg(test)]
-#[cfg(feature = "check-signature")]
 mod tests {
-    use hex::ToHex;
-    use ring::{rand, signature};
-    use ring::signature::KeyPair;
-    use super::ApplicationDescriptor;
+    use super::{ApplicationDescriptor, ChecksumAlgorithm, JvmParameters, SplashClosePolicy, TEST_DESCRIPTOR};
 
     #[test]
+    #[cfg(feature = "check-signature")]
     fn test_signature_verification() {
+        use hex::ToHex;
+        use ring::{rand, signature};
+        use ring::signature::KeyPair;
+
         let rng = rand::SystemRandom::new();
         let pkcs8_bytes = signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
         let key_pair = signature::Ed25519KeyPair::from_pkcs8(pkcs8_bytes.as_ref()).unwrap();
@@ -153,4 +492,213 @@ mod tests {
         let result = ApplicationDescriptor::verify(&content, &Some(String::from(signature)), peer_public_key_bytes);
         assert_eq!(true, result.is_ok());
     }
+
+    #[test]
+    fn test_parse_validation() {
+        let content = TEST_DESCRIPTOR.replace("lib/app.jar", "../app.jar");
+        let error = ApplicationDescriptor::parse(&content, "app", None).err().unwrap();
+        assert_eq!("Signature error: Descriptor defines storage location ../app.jar outside application directory. Please inform author about this security incident!",
+                   error.to_string());
+
+        let valid_content = content.replace("../app.jar", "lib/app.jar");
+        assert!(ApplicationDescriptor::parse(&valid_content, "app", None).is_ok());
+
+        let newer_content = format!("schema_version = 2\n{}", valid_content.trim_start());
+        let error = ApplicationDescriptor::parse(&newer_content, "app", None).err().unwrap();
+        assert_eq!("Could not parse descriptor: The application requires a newer launcher (schema version 2). Please download the latest version of the launcher.", error.to_string());
+        let current_content = format!("schema_version = 1\n{}", valid_content.trim_start());
+        assert!(ApplicationDescriptor::parse(&current_content, "app", None).is_ok());
+
+        // unknown fields are only accepted in lenient mode
+        let unknown_field_content = valid_content.replace("size = 4\n", "size = 4\nsizes = 2\n");
+        assert_eq!(cfg!(feature = "strict-descriptor"), ApplicationDescriptor::parse(&unknown_field_content, "app", None).is_err());
+
+        let error = ApplicationDescriptor::parse(&valid_content, "other", None).err().unwrap();
+        assert_eq!("Could not parse descriptor: Descriptor is for application app, but other was expected", error.to_string());
+
+        let jvm_content = valid_content.replace("server/jvm.dll", "../../../usr/lib/libevil.so");
+        let error = ApplicationDescriptor::parse(&jvm_content, "app", None).err().unwrap();
+        assert_eq!("Signature error: Descriptor defines JVM location jvm/bin/../../../usr/lib/libevil.so outside application directory. Please inform author about this security incident!",
+                   error.to_string());
+        let jvm_content = valid_content.replace("jvm/bin", "/usr/lib/jvm");
+        assert!(ApplicationDescriptor::parse(&jvm_content, "app", None).is_err());
+        let jvm_content = valid_content.replace("server/jvm.dll", "/usr/lib/libevil.so");
+        let error = ApplicationDescriptor::parse(&jvm_content, "app", None).err().unwrap();
+        assert_eq!("Signature error: Descriptor defines JVM library /usr/lib/libevil.so outside JVM directory. Please inform author about this security incident!",
+                   error.to_string());
+        let jvm_content = valid_content.replace("server/jvm.dll", "../lib/server/libjvm.so");
+        assert!(ApplicationDescriptor::parse(&jvm_content, "app", None).is_err());
+        let jvm_content = valid_content.replace("server/jvm.dll", "server//jvm.dll");
+        assert!(ApplicationDescriptor::parse(&jvm_content, "app", None).is_err());
+
+        let post_install_content = format!("post_install = [\"lib/app.jar\", \"--register\"]\n{}", valid_content.trim_start());
+        assert!(ApplicationDescriptor::parse(&post_install_content, "app", None).is_ok());
+        let post_install_content = format!("post_install = [\"lib/setup.sh\"]\n{}", valid_content.trim_start());
+        assert_eq!("Could not parse descriptor: Post-install command lib/setup.sh is not part of a component",
+                   ApplicationDescriptor::parse(&post_install_content, "app", None).err().unwrap().to_string());
+        let post_install_content = format!("post_install = [\"/bin/sh\"]\n{}", valid_content.trim_start());
+        assert!(ApplicationDescriptor::parse(&post_install_content, "app", None).is_err());
+
+        let duplicate_content = format!("{}\n[[component]]\nurl = \"https://example.com/app2.jar\"\nsize = 1\nchecksum = \"abc\"\npath = \"lib/./App.jar\"\n", valid_content);
+        assert_eq!("Could not parse descriptor: Paths lib/app.jar and lib/./App.jar overlap, each component needs its own location",
+                   ApplicationDescriptor::parse(&duplicate_content, "app", None).err().unwrap().to_string());
+        let nested_content = duplicate_content.replace("lib/./App.jar", "lib/");
+        assert!(ApplicationDescriptor::parse(&nested_content, "app", None).is_err());
+        let cache_content = valid_content.replace("path = \"lib/app.jar\"", "path = \"lib/app.jar\"\n            cache_path = \"splash/cache\"");
+        assert!(ApplicationDescriptor::parse(&cache_content, "app", None).is_err());
+        let separate_content = duplicate_content.replace("lib/./App.jar", "lib/app2.jar");
+        assert!(ApplicationDescriptor::parse(&separate_content, "app", None).is_ok());
+
+        let download_checksum_content = valid_content.replace("path = \"lib/app.jar\"", "path = \"lib/app.jar\"\n            download_checksum = \"abc\"");
+        assert_eq!("Could not parse descriptor: download_checksum of lib/app.jar is only supported for archives",
+                   ApplicationDescriptor::parse(&download_checksum_content, "app", None).err().unwrap().to_string());
+
+        let working_dir_content = valid_content.replace("options = []", "options = []\n            working_dir = \"data\"");
+        assert!(ApplicationDescriptor::parse(&working_dir_content, "app", None).is_ok());
+        let await_ui_content = valid_content.replace("options = []", "options = []\n            await_ui = false");
+        assert_eq!(Some(false), ApplicationDescriptor::parse(&await_ui_content, "app", None).unwrap().jvm_params.await_ui);
+        let splash_close_content = valid_content.replace("options = []", "options = []\n            splash_close = \"manual\"");
+        assert_eq!(Some(SplashClosePolicy::Manual), ApplicationDescriptor::parse(&splash_close_content, "app", None).unwrap().jvm_params.splash_close);
+        let splash_close_content = valid_content.replace("options = []", "options = []\n            splash_close = { timeout = 3000 }");
+        assert_eq!(Some(SplashClosePolicy::Timeout(3000)), ApplicationDescriptor::parse(&splash_close_content, "app", None).unwrap().jvm_params.splash_close);
+        let splash_close_content = valid_content.replace("options = []", "options = []\n            splash_close = \"never\"");
+        assert!(ApplicationDescriptor::parse(&splash_close_content, "app", None).is_err());
+        let working_dir_content = valid_content.replace("options = []", "options = []\n            working_dir = \"../data\"");
+        assert!(ApplicationDescriptor::parse(&working_dir_content, "app", None).is_err());
+
+        let env_content = valid_content.replace("options = []", "options = []\n            env = { APP_CONFIG = \"{installation_dir}/config\" }");
+        let descriptor = ApplicationDescriptor::parse(&env_content, "app", None).unwrap();
+        assert_eq!(Some("{installation_dir}/config"), descriptor.jvm_params.env.unwrap().get("APP_CONFIG").map(String::as_str));
+        let env_content = valid_content.replace("options = []", "options = []\n            env = { \"A=B\" = \"1\" }");
+        assert_eq!("Could not parse descriptor: Invalid environment variable \"A=B\"",
+                   ApplicationDescriptor::parse(&env_content, "app", None).err().unwrap().to_string());
+
+        let memory_content = valid_content.replace("options = []", "options = [\"-Dfile.encoding=UTF-8\"]\n            max_heap = \"2G\"\n            min_heap = \"512MB\"\n            stack_size = \"1m\"");
+        let descriptor = ApplicationDescriptor::parse(&memory_content, "app", None).unwrap();
+        assert_eq!(vec!["-Xmx2g", "-Xms512m", "-Xss1m", "-Dfile.encoding=UTF-8"], descriptor.jvm_params.all_options());
+        let memory_content = valid_content.replace("options = []", "options = []\n            max_heap = \"2 gigs\"");
+        assert_eq!("Could not parse descriptor: Invalid max_heap 2 gigs, expected a size like 512m or 2g",
+                   ApplicationDescriptor::parse(&memory_content, "app", None).err().unwrap().to_string());
+        let memory_content = valid_content.replace("options = []", "options = []\n            max_heap = \"512\"");
+        assert!(ApplicationDescriptor::parse(&memory_content, "app", None).is_err());
+        let memory_content = valid_content.replace("options = []", "options = []\n            max_heap = \"512m\"\n            min_heap = \"1g\"");
+        assert_eq!("Could not parse descriptor: min_heap 1g is larger than max_heap 512m",
+                   ApplicationDescriptor::parse(&memory_content, "app", None).err().unwrap().to_string());
+        let memory_content = valid_content.replace("options = []", "options = [\"-Xmx1g\"]\n            max_heap = \"2g\"");
+        assert!(ApplicationDescriptor::parse(&memory_content, "app", None).is_err());
+    }
+
+    #[test]
+    fn test_parse_memory_size() {
+        assert_eq!(Some((String::from("512m"), 512 << 20)), JvmParameters::parse_memory_size("512m"));
+        assert_eq!(Some((String::from("2g"), 2 << 30)), JvmParameters::parse_memory_size(" 2G "));
+        assert_eq!(Some((String::from("64k"), 64 << 10)), JvmParameters::parse_memory_size("64KB"));
+        assert_eq!(Some((String::from("1048576"), 1 << 20)), JvmParameters::parse_memory_size("1048576"));
+        assert_eq!(None, JvmParameters::parse_memory_size(""));
+        assert_eq!(None, JvmParameters::parse_memory_size("m"));
+        assert_eq!(None, JvmParameters::parse_memory_size("512b"));
+        assert_eq!(None, JvmParameters::parse_memory_size("1.5g"));
+        assert_eq!(None, JvmParameters::parse_memory_size("-1g"));
+        assert_eq!(None, JvmParameters::parse_memory_size("99999999999999999999g"));
+    }
+
+    #[test]
+    fn test_component_type() {
+        let content = r#"
+            name = "app"
+            version = "1.0"
+
+            [splash]
+            url = "https://example.com/splash.tar.zstd"
+            size = 1
+            checksum = "abc"
+            path = "splash"
+            type = "archive"
+
+            [jvm]
+            path = "jvm/bin"
+            library = "server/jvm.dll"
+            main = "app/Main"
+            options = []
+
+            [[component]]
+            url = "https://example.com/jvm.tar.zstd"
+            size = 1
+            checksum = "abc"
+            path = "jvm/"
+
+            [[component]]
+            url = "https://example.com/app.jar"
+            size = 1
+            checksum = "abc"
+            path = "lib/app.jar"
+        "#;
+        let descriptor = ApplicationDescriptor::parse(content, "app", None).unwrap();
+        assert!(descriptor.splash.unwrap().is_archive());
+        assert!(descriptor.components[0].is_archive());
+        assert!(!descriptor.components[1].is_archive());
+
+        let file_content = content.replace("path = \"jvm/\"", "path = \"jvm/\"\n            type = \"file\"");
+        assert!(!ApplicationDescriptor::parse(&file_content, "app", None).unwrap().components[0].is_archive());
+
+        let invalid_content = content.replace("type = \"archive\"", "type = \"zip\"");
+        assert!(ApplicationDescriptor::parse(&invalid_content, "app", None).is_err());
+    }
+
+    #[test]
+    fn test_without_splash() {
+        let content = r#"
+            name = "app"
+            version = "1.0"
+
+            [jvm]
+            path = "jvm/bin"
+            library = "server/jvm.dll"
+            main = "app/Main"
+            options = []
+
+            [[component]]
+            url = "https://example.com/app.jar"
+            size = 1
+            checksum = "abc"
+            path = "lib/app.jar"
+        "#;
+        let descriptor = ApplicationDescriptor::parse(content, "app", None).unwrap();
+        assert!(descriptor.splash.is_none());
+        assert_eq!(1, descriptor.all_components().len());
+    }
+
+    #[test]
+    fn test_checksum_algorithm() {
+        let content = TEST_DESCRIPTOR.replace("path = \"lib/app.jar\"", "path = \"lib/app.jar\"\nchecksum_algorithm = \"blake3\"");
+        let descriptor = ApplicationDescriptor::parse(&content, "app", None).unwrap();
+        assert_eq!(ChecksumAlgorithm::Blake3, descriptor.splash.as_ref().unwrap().checksum_algorithm());
+        assert_eq!(ChecksumAlgorithm::Blake3, descriptor.components[0].checksum_algorithm());
+
+        let sha256_content = content.replace("\"blake3\"", "\"sha256\"");
+        let result = ApplicationDescriptor::parse(&sha256_content, "app", None);
+        if cfg!(feature = "sha256") {
+            assert_eq!(ChecksumAlgorithm::Sha256, result.unwrap().components[0].checksum_algorithm());
+        } else {
+            assert!(result.unwrap_err().to_string().contains("not supported"));
+        }
+
+        let invalid_content = content.replace("\"blake3\"", "\"md5\"");
+        assert!(ApplicationDescriptor::parse(&invalid_content, "app", None).is_err());
+    }
+
+    #[test]
+    fn test_is_relative_inside() {
+        assert!(ApplicationDescriptor::is_relative_inside("lib/app.jar"));
+        assert!(ApplicationDescriptor::is_relative_inside("lib/foo..bar.jar"));
+        assert!(ApplicationDescriptor::is_relative_inside("jvm/"));
+        assert!(ApplicationDescriptor::is_relative_inside("lib/../app.jar"));
+        assert!(!ApplicationDescriptor::is_relative_inside("../app.jar"));
+        assert!(!ApplicationDescriptor::is_relative_inside("lib/../../app.jar"));
+        assert!(!ApplicationDescriptor::is_relative_inside("lib\\..\\..\\app.jar"));
+        assert!(!ApplicationDescriptor::is_relative_inside("/usr/lib/app.jar"));
+        assert!(!ApplicationDescriptor::is_relative_inside("\\\\server\\share\\app.jar"));
+        assert!(!ApplicationDescriptor::is_relative_inside("C:\\Windows\\app.jar"));
+        assert!(!ApplicationDescriptor::is_relative_inside("C:app.jar"));
+    }
 }
@@ -1,67 +1,318 @@
+use std::env;
 use std::fs;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 
+use attohttpc::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_RANGE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LOCATION, RANGE};
+use attohttpc::{Response, StatusCode};
 use log::*;
 use progress_streams::ProgressReader;
+use serde_derive::*;
 use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use tar::Archive;
+use url::{Origin, Url};
 
+use crate::clock::{Clock, SystemClock};
 use crate::descriptor::ApplicationComponent;
 use crate::errors::*;
-use crate::installation_manager::InstallationManager;
+use crate::installation_manager::{hash_reader, InstallationManager};
 use crate::recompress::recompress;
 use crate::UserInterface;
 
-pub struct DownloadManager {}
+const MAX_REDIRECTS_ENV: &str = "NATIVESTART_MAX_REDIRECTS";
+const HTTP_HEADERS_ENV: &str = "NATIVESTART_HTTP_HEADERS";
+const BEARER_TOKEN_ENV: &str = "NATIVESTART_BEARER_TOKEN";
+const ALLOW_HTTP_ENV: &str = "NATIVESTART_ALLOW_HTTP";
+/// first bytes of a zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+/// components with at least this download size are downloaded with parallel range requests if the server supports them
+const SEGMENTED_DOWNLOAD_MIN_SIZE: u64 = 32 * 1024 * 1024;
+/// number of concurrent range requests of a segmented download
+const DOWNLOAD_SEGMENTS: u64 = 4;
+
+pub struct DownloadManager {
+    max_redirections: u32,
+    /// origin of the application descriptor, the only origin receiving the custom headers
+    origin: Option<Origin>,
+    headers: HeaderMap,
+    /// allow plain HTTP downloads, e.g. for local testing
+    allow_http: bool,
+    /// allow reading local files (`file://` URLs and absolute paths), a remote descriptor must not copy arbitrary files
+    /// of the machine into the installation
+    allow_local: bool,
+    clock: Arc<dyn Clock>,
+}
+
+/// HTTP validators of a previous download used to avoid downloading unchanged content
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct HttpCacheInfo {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Download rate smoothed over time to avoid jitter, especially at the start of a transfer
+struct DownloadSpeed {
+    last_sample: Instant,
+    last_bytes: u64,
+    bytes_per_second: Option<f64>,
+}
+
+impl DownloadSpeed {
+    const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+    /// weight of a new sample in the exponential moving average
+    const SMOOTHING: f64 = 0.2;
+
+    fn new(now: Instant) -> DownloadSpeed {
+        return DownloadSpeed {
+            last_sample: now,
+            last_bytes: 0,
+            bytes_per_second: None,
+        };
+    }
+
+    /// Updates the rate with the total number of downloaded bytes and returns the smoothed rate if known
+    fn update(&mut self, downloaded_bytes: u64, now: Instant) -> Option<f64> {
+        let elapsed = now.duration_since(self.last_sample);
+        if elapsed >= DownloadSpeed::SAMPLE_INTERVAL {
+            let rate = downloaded_bytes.saturating_sub(self.last_bytes) as f64 / elapsed.as_secs_f64();
+            self.bytes_per_second = Some(match self.bytes_per_second {
+                Some(previous) => previous + DownloadSpeed::SMOOTHING * (rate - previous),
+                None => rate
+            });
+            self.last_sample = now;
+            self.last_bytes = downloaded_bytes;
+        }
+        return self.bytes_per_second;
+    }
+}
+
+/// File removed once it is no longer needed, e.g. assembled by a segmented download or an archive to verify
+struct TemporaryFile(PathBuf);
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Reader failing as soon as the user cancelled the download
+struct CancellableReader<'a, R: Read> {
+    reader: R,
+    ui: &'a UserInterface,
+}
+
+impl<'a, R: Read> Read for CancellableReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.ui.is_cancelled() {
+            return Err(io::Error::new(io::ErrorKind::Other, "download cancelled by user"));
+        }
+        return self.reader.read(buf);
+    }
+}
 
 impl DownloadManager {
-    pub fn new() -> DownloadManager {
-        return DownloadManager {};
+    const DEFAULT_MAX_REDIRECTIONS: u32 = 10;
+
+    /// Creates a download manager sending the given headers (e.g. `Authorization`) with all requests to the origin
+    /// of the application descriptor
+    pub fn new(application_descriptor_url: &str, headers: Vec<(String, String)>) -> DownloadManager {
+        // the maximum number of HTTP redirects per download can be overridden for unusual setups
+        let max_redirections = env::var(MAX_REDIRECTS_ENV).ok()
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or(DownloadManager::DEFAULT_MAX_REDIRECTIONS);
+
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(&value)) {
+                (Ok(name), Ok(value)) => { header_map.insert(name, value); },
+                _ => warn!("Ignoring invalid HTTP header {}", name)
+            }
+        }
+
+        let allow_http = env::var(ALLOW_HTTP_ENV).map_or(false, |value| value == "1" || value.eq_ignore_ascii_case("true"));
+        return DownloadManager {
+            max_redirections,
+            origin: Url::parse(application_descriptor_url).ok().map(|url| url.origin()),
+            headers: header_map,
+            allow_http,
+            allow_local: allow_http || DownloadManager::local_path(application_descriptor_url).is_some(),
+            clock: Arc::new(SystemClock),
+        };
+    }
+
+    /// Download manager without redirects and custom headers, allowing plain HTTP and local files of a test
+    #[cfg(test)]
+    pub fn for_test() -> DownloadManager {
+        return DownloadManager {
+            max_redirections: 0,
+            origin: None,
+            headers: HeaderMap::new(),
+            allow_http: true,
+            allow_local: true,
+            clock: Arc::new(SystemClock),
+        };
+    }
+
+    /// Reads the custom HTTP headers from the environment. `NATIVESTART_HTTP_HEADERS` contains `Name: value`
+    /// lines, `NATIVESTART_BEARER_TOKEN` a token for the `Authorization` header.
+    pub fn headers_from_env() -> Vec<(String, String)> {
+        let mut headers: Vec<(String, String)> = env::var(HTTP_HEADERS_ENV).unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (String::from(name.trim()), String::from(value.trim())))
+            .collect();
+        if let Ok(token) = env::var(BEARER_TOKEN_ENV) {
+            headers.push((AUTHORIZATION.to_string(), format!("Bearer {}", token)));
+        }
+        return headers;
     }
 
     /// Try to download the content from a specified URL
-    pub fn download_and_get(&self, url: &str) -> Option<String> {
-        let answer = attohttpc::get(url).send().ok()?;
+    pub fn download_and_get(&self, url: &str) -> Result<String> {
+        if let Some(path) = DownloadManager::local_path(url) {
+            self.check_local(url)?;
+            return fs::read_to_string(&path)
+                .map_err(|e| ErrorKind::DownloadError(format!("Could not read {:?}: {}", path, e)).into());
+        }
+        let answer = self.send(url, HeaderMap::new())?;
+        return answer.text()
+            .map_err(|e| ErrorKind::NetworkError(format!("Could not read {}: {}", url, e)).into());
+    }
+
+    /// Checks if the error is caused by an unreachable server (e.g. no network connection), in contrast to an
+    /// error status of the server (`HttpStatusError`)
+    pub fn is_unreachable(error: &Error) -> bool {
+        return matches!(error.kind(), ErrorKind::NetworkError(_));
+    }
 
-        if answer.is_success() {
-            return Some(answer.text().ok()?);
-        } else {
-            return Option::None;
+    /// Try to download the content from a specified URL unless it did not change since the download described
+    /// by `cache_info`. Returns `None` if the content is unchanged.
+    pub fn download_and_get_if_modified(&self, url: &str, cache_info: &HttpCacheInfo) -> Result<Option<(String, HttpCacheInfo)>> {
+        if DownloadManager::local_path(url).is_some() {
+            return Ok(Some((self.download_and_get(url)?, HttpCacheInfo::default())));
         }
+
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = cache_info.etag.as_ref().and_then(|value| HeaderValue::from_str(value).ok()) {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = cache_info.last_modified.as_ref().and_then(|value| HeaderValue::from_str(value).ok()) {
+            headers.insert(IF_MODIFIED_SINCE, last_modified);
+        }
+        let answer = self.send(url, headers)?;
+        if answer.status() == StatusCode::NOT_MODIFIED {
+            debug!("{} has not been modified", url);
+            return Ok(None);
+        }
+
+        let header = |name| answer.headers().get(name).and_then(|value: &HeaderValue| value.to_str().ok()).map(String::from);
+        let new_cache_info = HttpCacheInfo {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        let content = answer.text()
+            .map_err(|e| Error::from(ErrorKind::NetworkError(format!("Could not read {}: {}", url, e))))?;
+        return Ok(Some((content, new_cache_info)));
     }
 
     pub fn download_and_store(&self, components: &Vec<ApplicationComponent>, installation: &InstallationManager, ui: &UserInterface) -> Result<()> {
         let mut downloaded: u64 = 0;
         let total_size: u64 = components.iter().map(|ref component| component.download_size.unwrap_or(component.size)).sum();
         info!("Downloading {} components ({} bytes)", components.len(), total_size);
-        for component in components {
+        let speed = Mutex::new(DownloadSpeed::new(self.clock.now()));
+        for (index, component) in components.iter().enumerate() {
             let path = installation.path_for_write(&component)?;
+            let file_size = component.download_size.unwrap_or(component.size);
+            ui.set_download_file(index + 1, components.len(), file_size);
 
             debug!("Downloading {} to {:?}", component.url, path);
 
-            // prepare HTTP client
-            let res = attohttpc::get(&component.url).send()
-                .chain_err(|| ErrorKind::DownloadError(format!("Could not download file {:?}", &component.url)))?;
-
-            // decorate reader with progress tracking
             let file_progress = Arc::new(AtomicUsize::new(0));
-            let mut reader = ProgressReader::new(res, |progress: usize| {
+            let report_progress = |progress: usize| {
                 file_progress.fetch_add(progress, Ordering::SeqCst);
-                ui.set_download_progress((downloaded + file_progress.load(Ordering::SeqCst) as u64) as f64 / total_size as f64);
-            });
+                let file_downloaded = file_progress.load(Ordering::SeqCst) as u64;
+                ui.set_file_progress(file_downloaded);
+                if component.is_archive() && file_downloaded >= file_size {
+                    // the end of the archive is still being unpacked
+                    ui.set_extracting(true);
+                }
+                let current = downloaded + file_downloaded;
+                if let Some(bytes_per_second) = speed.lock().unwrap().update(current, self.clock.now()) {
+                    ui.set_download_speed(bytes_per_second as u64);
+                }
+                ui.set_download_progress(current, total_size);
+            };
+
+            // open local file, download large files in segments or prepare HTTP client
+            let mut segments_file: Option<TemporaryFile> = None;
+            let res: Box<dyn Read> = match DownloadManager::local_path(&component.url) {
+                Some(local_path) => {
+                    self.check_local(&component.url)?;
+                    Box::new(File::open(&local_path)
+                        .chain_err(|| ErrorKind::DownloadError(format!("Could not open file {:?}", &local_path)))?)
+                }
+                None if file_size >= SEGMENTED_DOWNLOAD_MIN_SIZE => {
+                    let mut segments_name = path.file_name().unwrap_or_default().to_os_string();
+                    segments_name.push(".segments");
+                    let segments_path = path.with_file_name(segments_name);
+                    match self.download_segmented(&component.url, file_size, &segments_path, DOWNLOAD_SEGMENTS, &report_progress, ui)? {
+                        Some(file) => {
+                            segments_file = Some(TemporaryFile(segments_path));
+                            Box::new(file)
+                        }
+                        None => Box::new(self.send(&component.url, HeaderMap::new())?)
+                    }
+                }
+                None => Box::new(self.send(&component.url, HeaderMap::new())?),
+            };
 
-            if component.is_archive() {
-                // create empty directory
-                fs::create_dir_all(&path)
-                    .chain_err(|| ErrorKind::StorageError(format!("Could not create directory {:?}", &path)))?;
+            // decorate reader with progress tracking, segments report their progress while downloading
+            let segmented = segments_file.is_some();
+            let res = CancellableReader { reader: res, ui };
+            let mut reader = ProgressReader::new(res, |progress: usize| {
+                if !segmented {
+                    report_progress(progress);
+                }
+            });
 
-                // extract data stream to target location
-                let stream = zstd::Decoder::new(reader)?;
-                let mut archive = Archive::new(stream);
-                archive.unpack(&path)
-                    .chain_err(|| ErrorKind::StorageError(format!("Could not unpack compressed file {:?}", &path)))?;
+            if let (true, Some(download_checksum)) = (component.is_archive(), &component.download_checksum) {
+                // no byte of the archive is unpacked before the whole archive is verified
+                let mut archive_name = path.file_name().unwrap_or_default().to_os_string();
+                archive_name.push(".download");
+                let archive_file = TemporaryFile(path.with_file_name(archive_name));
+                File::create(&archive_file.0)
+                    .and_then(|mut file| io::copy(&mut reader, &mut file))
+                    .chain_err(|| ErrorKind::NetworkError(format!("Error during download")))?;
+                DownloadManager::check_download_size(component, file_progress.load(Ordering::SeqCst) as u64)?;
+                let checksum = File::open(&archive_file.0)
+                    .and_then(|file| hash_reader(file, component.checksum_algorithm()))
+                    .chain_err(|| ErrorKind::StorageError(format!("Could not read {:?}", &archive_file.0)))?;
+                if checksum != *download_checksum {
+                    bail!(ErrorKind::DownloadError(format!("Checksum of {} does not match (expected {}, actual {})",
+                        component.url, download_checksum, checksum)));
+                }
+                let archive = File::open(&archive_file.0)
+                    .chain_err(|| ErrorKind::StorageError(format!("Could not read {:?}", &archive_file.0)))?;
+                DownloadManager::unpack_archive(CancellableReader { reader: archive, ui }, &path, component.strip_components.unwrap_or(0), || {
+                    if ui.is_cancelled() {
+                        bail!(ErrorKind::Cancelled);
+                    }
+                    return Ok(());
+                })?;
+            } else if component.is_archive() {
+                // a truncated archive must not replace the installed directory
+                DownloadManager::unpack_archive(&mut reader, &path, component.strip_components.unwrap_or(0), || {
+                    // the end of the archive may be unpacked after the last read, a cancelled archive must not be moved into place
+                    if ui.is_cancelled() {
+                        bail!(ErrorKind::Cancelled);
+                    }
+                    return DownloadManager::check_download_size(component, file_progress.load(Ordering::SeqCst) as u64);
+                })?;
             } else {
                 // create parent directories if needed
                 path.parent().and_then(|parent| fs::create_dir_all(parent).ok());
@@ -70,13 +321,18 @@ impl DownloadManager {
 
                 // special handling for zstd-compressed JAR files
                 if component.url.ends_with(".jar.zstd") && path.to_str().unwrap().ends_with(".jar") {
-                    let mut stream = zstd::Decoder::new(reader)?;
+                    let mut stream = zstd::Decoder::new(&mut reader)?;
                     recompress(&mut stream, &mut file).unwrap();
+                    drop(stream);
+                    io::copy(&mut reader, &mut io::sink()).chain_err(|| ErrorKind::NetworkError(format!("Error during download")))?;
                 } else {
-                    io::copy(&mut reader, &mut file).chain_err(|| ErrorKind::DownloadError(format!("Error during download")))?;
+                    io::copy(&mut reader, &mut file).chain_err(|| ErrorKind::NetworkError(format!("Error during download")))?;
                 }
+                DownloadManager::check_download_size(component, file_progress.load(Ordering::SeqCst) as u64)?;
             }
 
+            installation.apply_mode(component)?;
+
             // re-create cache directory if there is one
             match &component.cache_path {
                 Some(cache_path) => installation.recreate_dir(cache_path)?,
@@ -84,10 +340,767 @@ impl DownloadManager {
             }
 
             downloaded += component.download_size.unwrap_or(component.size);
-            ui.set_download_progress(downloaded as f64 / total_size as f64);
+            ui.set_download_progress(downloaded, total_size);
         }
 
         ui.download_done();
         return Ok(());
     }
+
+    /// Downloads the file with parallel range requests into `segments_path` and returns it for reading. Returns `None`
+    /// if the server does not support range requests, the file is then downloaded as a single stream.
+    fn download_segmented<F: Fn(usize) + Sync>(&self, url: &str, size: u64, segments_path: &Path, segments: u64,
+                                               report_progress: &F, ui: &UserInterface) -> Result<Option<File>> {
+        let segment_size = size.div_ceil(segments);
+        let ranges: Vec<(u64, u64)> = (0..segments)
+            .map(|segment| (segment * segment_size, ((segment + 1) * segment_size).min(size)))
+            .filter(|(start, end)| start < end)
+            .collect();
+
+        // the response to the first range tells if the server supports ranges
+        let first_response = match self.send_range(url, ranges[0], size)? {
+            Some(response) => response,
+            None => {
+                debug!("Server does not support range requests for {}, downloading as single stream", url);
+                return Ok(None);
+            }
+        };
+        debug!("Downloading {} in {} segments", url, ranges.len());
+        File::create(segments_path)
+            .and_then(|file| file.set_len(size))
+            .chain_err(|| ErrorKind::StorageError(format!("Could not create file {:?}", segments_path)))?;
+
+        let result = thread::scope(|scope| {
+            let handles: Vec<_> = ranges[1..].iter()
+                .map(|range| scope.spawn(move || self.download_segment(url, None, *range, size, segments_path, report_progress, ui)))
+                .collect();
+            let mut result = self.download_segment(url, Some(first_response), ranges[0], size, segments_path, report_progress, ui);
+            for handle in handles {
+                let segment_result = handle.join()
+                    .unwrap_or_else(|_| Err(ErrorKind::DownloadError(format!("Download of segment of {} failed", url)).into()));
+                result = result.and(segment_result);
+            }
+            return result;
+        });
+        if let Err(e) = result {
+            let _ = fs::remove_file(segments_path);
+            return Err(e);
+        }
+        let file = File::open(segments_path)
+            .chain_err(|| ErrorKind::StorageError(format!("Could not open file {:?}", segments_path)))?;
+        return Ok(Some(file));
+    }
+
+    /// Downloads the bytes from `start` (inclusive) to `end` (exclusive) into the segments file, the response to the
+    /// range request may already be given
+    fn download_segment<F: Fn(usize)>(&self, url: &str, response: Option<Response>, (start, end): (u64, u64), size: u64,
+                                      segments_path: &Path, report_progress: &F, ui: &UserInterface) -> Result<()> {
+        let response = match response {
+            Some(response) => response,
+            None => self.send_range(url, (start, end), size)?
+                .chain_err(|| ErrorKind::DownloadError(format!("Server did not return bytes {}-{} of {}", start, end - 1, url)))?
+        };
+        let mut file = OpenOptions::new().write(true).open(segments_path)
+            .chain_err(|| ErrorKind::StorageError(format!("Could not open file {:?}", segments_path)))?;
+        file.seek(SeekFrom::Start(start))
+            .chain_err(|| ErrorKind::StorageError(format!("Could not write file {:?}", segments_path)))?;
+
+        let reader = CancellableReader { reader: response, ui };
+        let mut reader = ProgressReader::new(reader, |progress: usize| report_progress(progress)).take(end - start);
+        let received = io::copy(&mut reader, &mut file).chain_err(|| ErrorKind::NetworkError(format!("Error during download")))?;
+        if received != end - start {
+            bail!(ErrorKind::DownloadError(format!("Download of bytes {}-{} of {} is incomplete (received {} bytes)",
+                start, end - 1, url, received)));
+        }
+        return Ok(());
+    }
+
+    /// Requests the bytes from `start` (inclusive) to `end` (exclusive) of a file with the given size. Returns `None`
+    /// if the server ignores the range and answers with the whole file.
+    fn send_range(&self, url: &str, (start, end): (u64, u64), size: u64) -> Result<Option<Response>> {
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, HeaderValue::from_str(&format!("bytes={}-{}", start, end - 1)).unwrap());
+        let response = self.send(url, headers)?;
+        let content_range = response.headers().get(CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(DownloadManager::parse_content_range);
+        if response.status() != StatusCode::PARTIAL_CONTENT || content_range != Some((start, end - 1, size)) {
+            return Ok(None);
+        }
+        return Ok(Some(response));
+    }
+
+    /// Parses a `Content-Range` header like `bytes 0-499/1234` into first byte, last byte and total size
+    fn parse_content_range(value: &str) -> Option<(u64, u64, u64)> {
+        let (range, size) = value.strip_prefix("bytes ")?.split_once('/')?;
+        let (first, last) = range.split_once('-')?;
+        return Some((first.trim().parse().ok()?, last.trim().parse().ok()?, size.trim().parse().ok()?));
+    }
+
+    /// Detects truncated downloads, the download size is optional in the descriptor
+    fn check_download_size(component: &ApplicationComponent, received: u64) -> Result<()> {
+        match component.download_size {
+            Some(download_size) if download_size != received => {
+                bail!(ErrorKind::DownloadError(format!("Download of {} is incomplete (expected {} bytes, received {} bytes)",
+                    component.url, download_size, received)));
+            }
+            _ => return Ok(())
+        }
+    }
+
+    /// Extract a zstd compressed tar archive to the given directory, removing the given number of leading path
+    /// components. The check is called once the whole stream has been read, the directory is only replaced if it succeeds.
+    fn unpack_archive<R: Read, F: FnOnce() -> Result<()>>(mut reader: R, path: &Path, strip_components: usize, check: F) -> Result<()> {
+        // extract to a temporary sibling directory first to never leave a half-unpacked directory behind
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".partial");
+        let temp_path = path.with_file_name(temp_name);
+        if temp_path.exists() {
+            fs::remove_dir_all(&temp_path)
+                .chain_err(|| ErrorKind::StorageError(format!("Could not remove directory {:?}", &temp_path)))?;
+        }
+        fs::create_dir_all(&temp_path)
+            .chain_err(|| ErrorKind::StorageError(format!("Could not create directory {:?}", &temp_path)))?;
+
+        // fail with a clear message if a component declared as archive is a plain file
+        let mut magic = [0u8; 4];
+        let is_zstd = reader.read_exact(&mut magic).is_ok() && magic == ZSTD_MAGIC;
+        if !is_zstd {
+            let _ = fs::remove_dir_all(&temp_path);
+            bail!(ErrorKind::DownloadError(format!("Download for {:?} is not a zstd compressed tar archive", &path)));
+        }
+
+        // extract data stream to temporary location
+        let unpacked = zstd::Decoder::new(io::Cursor::new(magic).chain(reader.by_ref()))
+            .chain_err(|| ErrorKind::StorageError(format!("Could not decompress archive for {:?}", &path)))
+            .and_then(|stream| {
+                let mut archive = Archive::new(stream);
+                // keep executable bits (e.g. of the java binaries) on Unix
+                archive.set_preserve_permissions(true);
+                if strip_components > 0 {
+                    return DownloadManager::unpack_stripped(&mut archive, &temp_path, strip_components)
+                        .chain_err(|| ErrorKind::StorageError(format!("Could not unpack compressed file {:?}", &path)));
+                }
+                return archive.unpack(&temp_path)
+                    .chain_err(|| ErrorKind::StorageError(format!("Could not unpack compressed file {:?}", &path)));
+            })
+            // the archive may end before the stream, e.g. with padding
+            .and_then(|_| io::copy(&mut reader, &mut io::sink())
+                .chain_err(|| ErrorKind::NetworkError(format!("Error during download of {:?}", &path))))
+            .and_then(|_| check());
+        if let Err(e) = unpacked {
+            let _ = fs::remove_dir_all(&temp_path);
+            return Err(e);
+        }
+        fs::rename(&temp_path, &path)
+            .chain_err(|| ErrorKind::StorageError(format!("Could not move {:?} to {:?}", &temp_path, &path)))?;
+        return Ok(());
+    }
+
+    /// Like `tar --strip-components`: entries are extracted without their leading directories (e.g. `jdk-17.0.1/`)
+    fn unpack_stripped<R: Read>(archive: &mut Archive<R>, destination: &Path, strip_components: usize) -> Result<()> {
+        let strip = |path: &Path| -> PathBuf {
+            return path.components()
+                .filter(|component| *component != Component::CurDir)
+                .skip(strip_components)
+                .collect();
+        };
+        // unlike Archive::unpack, Entry::unpack neither checks the path nor symbolic links extracted before
+        let canonical_destination = destination.canonicalize()?;
+        let outside = |entry_path: &Path| ErrorKind::StorageError(format!("Archive entry {:?} is outside of the directory", entry_path));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let entry_path = entry.path()?.into_owned();
+            let stripped = strip(&entry_path);
+            if stripped.as_os_str().is_empty() {
+                continue;
+            }
+            if !DownloadManager::is_relative_inside(&stripped) {
+                bail!(outside(&entry_path));
+            }
+            let target = destination.join(&stripped);
+            let parent = target.parent().unwrap_or(destination);
+            if !DownloadManager::is_inside(parent, &canonical_destination)? {
+                bail!(outside(&entry_path));
+            }
+            fs::create_dir_all(parent)?;
+
+            if entry.header().entry_type().is_hard_link() {
+                // link targets are relative to the archive root, they are stripped like the entry paths
+                let link_name = entry.link_name()?.map(|link_name| strip(&link_name)).unwrap_or_default();
+                let source = destination.join(&link_name);
+                if link_name.as_os_str().is_empty() || !DownloadManager::is_relative_inside(&link_name)
+                    || !DownloadManager::is_inside(source.parent().unwrap_or(destination), &canonical_destination)? {
+                    bail!(outside(&entry_path));
+                }
+                fs::hard_link(&source, &target)?;
+                continue;
+            }
+            entry.unpack(&target)?;
+        }
+        return Ok(());
+    }
+
+    /// Checks that a relative path has no parent or root components
+    fn is_relative_inside(path: &Path) -> bool {
+        return path.components().all(|component| matches!(component, Component::Normal(_)));
+    }
+
+    /// Checks that the existing part of the path does not lead outside of the directory through symbolic links
+    fn is_inside(path: &Path, canonical_directory: &Path) -> io::Result<bool> {
+        // the missing directories are created below the existing ones
+        let existing = match path.ancestors().find(|ancestor| fs::symlink_metadata(ancestor).is_ok()) {
+            Some(existing) => existing,
+            None => return Ok(false)
+        };
+        return Ok(existing.canonicalize()?.starts_with(canonical_directory));
+    }
+
+    /// Send a GET request following redirects and fail for unsuccessful responses. Redirects are followed manually
+    /// to avoid leaking the custom headers to other origins.
+    fn send(&self, url: &str, headers: HeaderMap) -> Result<Response> {
+        let mut current_url = Url::parse(url)
+            .map_err(|e| ErrorKind::DownloadError(format!("Invalid URL {}: {}", url, e)))?;
+        let mut redirections = 0;
+        loop {
+            // also checked for redirects to avoid a downgrade
+            self.check_scheme(&current_url)?;
+            let mut request = attohttpc::get(current_url.as_str()).follow_redirects(false);
+            request.headers_mut().extend(headers.clone());
+            if self.is_descriptor_origin(&current_url) {
+                request.headers_mut().extend(self.headers.clone());
+            }
+            let answer = request.send()
+                .map_err(|e| ErrorKind::NetworkError(format!("Could not download {}: {}", url, e)))?;
+
+            let is_redirect = matches!(answer.status(), StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND
+                | StatusCode::SEE_OTHER | StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT);
+            if is_redirect {
+                redirections += 1;
+                if redirections > self.max_redirections {
+                    bail!(ErrorKind::DownloadError(format!("Could not download {}: more than {} redirects", url, self.max_redirections)));
+                }
+                let location = answer.headers().get(LOCATION)
+                    .and_then(|value| value.to_str().ok())
+                    .chain_err(|| ErrorKind::DownloadError(format!("Could not download {}: redirect without location", url)))?;
+                current_url = current_url.join(location)
+                    .map_err(|e| ErrorKind::DownloadError(format!("Could not download {}: invalid redirect to {}: {}", url, location, e)))?;
+                debug!("Redirected to {}", current_url);
+                continue;
+            }
+
+            if !answer.is_success() && answer.status() != StatusCode::NOT_MODIFIED {
+                bail!(ErrorKind::HttpStatusError(answer.status().as_u16(), format!("Could not download {}: HTTP status {}", url, answer.status())));
+            }
+            return Ok(answer);
+        }
+    }
+
+    fn check_scheme(&self, url: &Url) -> Result<()> {
+        if url.scheme() != "https" && !self.allow_http {
+            bail!(ErrorKind::DownloadError(format!("Refusing to download {} without HTTPS (set {}=1 to allow)", url, ALLOW_HTTP_ENV)));
+        }
+        return Ok(());
+    }
+
+    fn check_local(&self, url: &str) -> Result<()> {
+        if !self.allow_local {
+            bail!(ErrorKind::DownloadError(format!("Refusing to read local file {} for a remote application descriptor (set {}=1 to allow)", url, ALLOW_HTTP_ENV)));
+        }
+        return Ok(());
+    }
+
+    fn is_descriptor_origin(&self, url: &Url) -> bool {
+        return self.origin.as_ref() == Some(&url.origin());
+    }
+
+    /// Returns the file system path for `file://` URLs and absolute paths
+    fn local_path(url: &str) -> Option<PathBuf> {
+        if let Some(path) = url.strip_prefix("file://") {
+            // file:///C:/dir/file on Windows
+            let path = if cfg!(windows) { path.trim_start_matches('/') } else { path };
+            return Some(PathBuf::from(path));
+        }
+        if Path::new(url).is_absolute() {
+            return Some(PathBuf::from(url));
+        }
+        return None;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use tempfile::TempDir;
+    use url::Url;
+    use std::time::{Duration, Instant};
+    use std::sync::Arc;
+    use crate::clock::ManualClock;
+    use crate::download_manager::{DownloadManager, DownloadSpeed};
+    use std::collections::{BTreeMap, HashMap};
+    use crate::descriptor::{ApplicationComponent, ApplicationDescriptor};
+    use crate::errors::ErrorKind;
+    use crate::installation_manager::{combine_checksums, InstallationManager};
+    use crate::installation_manager::CheckFailure;
+    use crate::installation_manager::CheckResult::{NotOk, OkLocked};
+    use crate::{Message, UserInterface};
+
+    #[test]
+    fn test_local_path() {
+        assert_eq!(DownloadManager::local_path("https://example.com/app.toml"), None);
+        assert_eq!(DownloadManager::local_path("app.toml"), None);
+        #[cfg(not(windows))]
+        {
+            assert_eq!(DownloadManager::local_path("file:///opt/app/app.toml"), Some(PathBuf::from("/opt/app/app.toml")));
+            assert_eq!(DownloadManager::local_path("/opt/app/app.toml"), Some(PathBuf::from("/opt/app/app.toml")));
+        }
+        #[cfg(windows)]
+        assert_eq!(DownloadManager::local_path("file:///C:/app/app.toml"), Some(PathBuf::from("C:/app/app.toml")));
+    }
+
+    #[test]
+    fn test_descriptor_origin() {
+        let download_manager = DownloadManager::new("https://example.com/app/app.toml",
+                                                    vec![(String::from("Authorization"), String::from("Bearer abc"))]);
+        assert_eq!(1, download_manager.headers.len());
+        assert!(download_manager.is_descriptor_origin(&Url::parse("https://example.com/app/lib.jar").unwrap()));
+        assert!(!download_manager.is_descriptor_origin(&Url::parse("http://example.com/app/lib.jar").unwrap()));
+        assert!(!download_manager.is_descriptor_origin(&Url::parse("https://example.com:8443/app/lib.jar").unwrap()));
+        assert!(!download_manager.is_descriptor_origin(&Url::parse("https://cdn.example.com/app/lib.jar").unwrap()));
+    }
+
+    #[test]
+    fn test_check_scheme() {
+        let mut download_manager = DownloadManager::new("https://example.com/app/app.toml", vec![]);
+        download_manager.allow_http = false;
+        assert!(download_manager.check_scheme(&Url::parse("https://example.com/app/lib.jar").unwrap()).is_ok());
+        assert!(download_manager.check_scheme(&Url::parse("http://example.com/app/lib.jar").unwrap()).is_err());
+
+        download_manager.allow_http = true;
+        assert!(download_manager.check_scheme(&Url::parse("http://example.com/app/lib.jar").unwrap()).is_ok());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_unpack_archive_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // create archive containing an executable
+        let mut builder = tar::Builder::new(Vec::new());
+        let content = "#!/bin/sh\n";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o777);
+        header.set_cksum();
+        builder.append_data(&mut header, "bin/java", content.as_bytes()).unwrap();
+        let archive = zstd::encode_all(builder.into_inner().unwrap().as_slice(), 0).unwrap();
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("jvm");
+        DownloadManager::unpack_archive(archive.as_slice(), &path, 0, || Ok(())).unwrap();
+
+        let mode = fs::metadata(path.join("bin/java")).unwrap().permissions().mode();
+        assert_eq!(0o777, mode & 0o777);
+        assert!(!temp_dir.path().join("jvm.partial").exists());
+    }
+
+    #[test]
+    fn test_unpack_truncated_archive() {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_cksum();
+        builder.append_data(&mut header, "lib/app.jar", "test".as_bytes()).unwrap();
+        let archive = zstd::encode_all(builder.into_inner().unwrap().as_slice(), 0).unwrap();
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("lib");
+        let component = ApplicationComponent {
+            url: String::from("https://example.com/lib.tar.zstd"),
+            download_size: Some(archive.len() as u64 + 10),
+            ..ApplicationComponent::for_test("lib/", 4, "")
+        };
+        let result = DownloadManager::unpack_archive(archive.as_slice(), &path, 0,
+            || DownloadManager::check_download_size(&component, archive.len() as u64));
+        assert!(matches!(result.unwrap_err().kind(), ErrorKind::DownloadError(_)));
+        assert!(!path.exists());
+        assert!(!temp_dir.path().join("lib.partial").exists());
+
+        assert!(DownloadManager::check_download_size(&component, archive.len() as u64 + 10).is_ok());
+    }
+
+    #[test]
+    fn test_unpack_archive_strip_components() {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, content) in [("jdk-17.0.1/bin/java", "java"), ("./jdk-17.0.1/lib/modules", "modules"), ("README", "readme")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, content.as_bytes()).unwrap();
+        }
+        let archive = zstd::encode_all(builder.into_inner().unwrap().as_slice(), 0).unwrap();
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("jvm");
+        DownloadManager::unpack_archive(archive.as_slice(), &path, 1, || Ok(())).unwrap();
+
+        assert_eq!("java", fs::read_to_string(path.join("bin/java")).unwrap());
+        assert_eq!("modules", fs::read_to_string(path.join("lib/modules")).unwrap());
+        // top level files have no directory to strip
+        assert!(!path.join("README").exists());
+        assert!(!path.join("jdk-17.0.1").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_unpack_archive_strip_components_links() {
+        let outside_dir = TempDir::new().unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("jvm");
+        let archive = |entries: &[(&str, tar::EntryType, &str)]| {
+            let mut builder = tar::Builder::new(Vec::new());
+            for (name, entry_type, target) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(*entry_type);
+                header.set_mode(0o644);
+                if entry_type.is_file() {
+                    header.set_size(target.len() as u64);
+                    header.set_cksum();
+                    builder.append_data(&mut header, name, target.as_bytes()).unwrap();
+                } else {
+                    header.set_size(0);
+                    builder.append_link(&mut header, name, target).unwrap();
+                }
+            }
+            return zstd::encode_all(builder.into_inner().unwrap().as_slice(), 0).unwrap();
+        };
+
+        // writing through a symbolic link to a directory outside of the installation
+        let hostile = archive(&[
+            ("jdk/conf", tar::EntryType::Symlink, outside_dir.path().to_str().unwrap()),
+            ("jdk/conf/evil.txt", tar::EntryType::Regular, "evil"),
+        ]);
+        let result = DownloadManager::unpack_archive(hostile.as_slice(), &path, 1, || Ok(()));
+        assert!(result.unwrap_err().to_string().contains("Could not unpack"));
+        assert!(!outside_dir.path().join("evil.txt").exists());
+        assert!(!path.exists());
+
+        // hard links outside of the archive root
+        let hostile = archive(&[("jdk/passwd", tar::EntryType::Link, "../../etc/passwd")]);
+        assert!(DownloadManager::unpack_archive(hostile.as_slice(), &path, 1, || Ok(())).is_err());
+        assert!(!path.exists());
+
+        // links within the archive are stripped as well
+        let valid = archive(&[
+            ("jdk/bin/java", tar::EntryType::Regular, "java"),
+            ("jdk/bin/java2", tar::EntryType::Link, "jdk/bin/java"),
+            ("jdk/lib/java", tar::EntryType::Symlink, "../bin/java"),
+        ]);
+        DownloadManager::unpack_archive(valid.as_slice(), &path, 1, || Ok(())).unwrap();
+        assert_eq!("java", fs::read_to_string(path.join("bin/java2")).unwrap());
+        assert_eq!("java", fs::read_to_string(path.join("lib/java")).unwrap());
+    }
+
+    #[test]
+    fn test_unpack_no_archive() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("lib");
+        let result = DownloadManager::unpack_archive("PK\x03\x04 plain jar".as_bytes(), &path, 0, || Ok(()));
+        assert!(result.unwrap_err().to_string().contains("is not a zstd compressed tar archive"));
+        assert!(!path.exists());
+        assert!(!temp_dir.path().join("lib.partial").exists());
+    }
+
+    #[test]
+    fn test_download_speed() {
+        let start = Instant::now();
+        let mut speed = DownloadSpeed::new(start);
+        // no rate before the first sample interval passed
+        assert_eq!(None, speed.update(10_000, start + Duration::from_millis(100)));
+        assert_eq!(Some(2_000_000.0), speed.update(1_000_000, start + Duration::from_millis(500)));
+        // a short stall only changes the smoothed rate a bit
+        assert_eq!(Some(1_600_000.0), speed.update(1_000_000, start + Duration::from_millis(1000)));
+        assert_eq!(Some(1_600_000.0), speed.update(1_100_000, start + Duration::from_millis(1200)));
+    }
+
+    #[test]
+    fn test_download_speed_clock() {
+        // every progress update happens a second after the previous one
+        let download_manager = DownloadManager {
+            clock: Arc::new(ManualClock::with_step(Duration::from_secs(1))),
+            ..DownloadManager::for_test()
+        };
+        let (tx, rx) = mpsc::channel();
+        let ui = UserInterface::new(tx);
+        let source_dir = TempDir::new().unwrap();
+        let source = source_dir.path().join("app.jar");
+        fs::write(&source, vec![0u8; 1000]).unwrap();
+        let temp_dir = TempDir::new().unwrap();
+        let installation = InstallationManager::with_root(temp_dir.path());
+        let component = ApplicationComponent {
+            url: String::from(source.to_str().unwrap()),
+            ..ApplicationComponent::for_test("lib/app.jar", 1000, "")
+        };
+
+        download_manager.download_and_store(&vec![component], &installation, &ui).unwrap();
+        let progress = rx.try_iter()
+            .find_map(|message| match message {
+                Message::Downloading(progress) => Some(progress),
+                _ => None
+            })
+            .unwrap();
+        assert!(progress.bytes_per_second.load(Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    fn test_download_local_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("app.toml");
+        fs::write(&path, "name = \"app\"").unwrap();
+
+        let download_manager = DownloadManager::new(path.to_str().unwrap(), vec![]);
+        assert_eq!(download_manager.download_and_get(path.to_str().unwrap()).unwrap(), "name = \"app\"");
+        assert!(download_manager.download_and_get(temp_dir.path().join("missing.toml").to_str().unwrap()).is_err());
+
+        // a remote descriptor must not read local files unless explicitly allowed
+        let mut download_manager = DownloadManager::new("https://example.com/app.toml", vec![]);
+        download_manager.allow_local = false;
+        let error = download_manager.download_and_get(path.to_str().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("Refusing to read local file"));
+        download_manager.allow_local = true;
+        assert_eq!(download_manager.download_and_get(&format!("file://{}", path.to_str().unwrap())).unwrap(), "name = \"app\"");
+    }
+
+    #[test]
+    fn test_download_errors() {
+        let download_manager = DownloadManager::for_test();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/app.toml", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+        });
+        let error = download_manager.download_and_get(&url).unwrap_err();
+        server.join().unwrap();
+        assert!(matches!(error.kind(), ErrorKind::HttpStatusError(404, _)));
+        assert!(!DownloadManager::is_unreachable(&error));
+        assert!(!error.kind().is_transient());
+
+        // nobody listens on the port anymore
+        let error = download_manager.download_and_get(&url).unwrap_err();
+        assert!(DownloadManager::is_unreachable(&error));
+        assert!(error.kind().is_transient());
+    }
+
+    /// Serves the content to the given number of requests, honouring `Range` headers if `ranges` is set
+    fn serve(content: Vec<u8>, requests: usize, ranges: bool) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/jvm.tar.zstd", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            for _ in 0..requests {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 1024];
+                let length = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..length]).to_lowercase();
+                let range = request.lines()
+                    .find_map(|line| line.strip_prefix("range: bytes="))
+                    .and_then(|range| range.trim().split_once('-'))
+                    .map(|(first, last)| (first.parse::<usize>().unwrap(), last.parse::<usize>().unwrap()));
+                let response = match range {
+                    Some((first, last)) if ranges => {
+                        let mut response = format!("HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            first, last, content.len(), last - first + 1).into_bytes();
+                        response.extend(&content[first..=last]);
+                        response
+                    }
+                    _ => {
+                        let mut response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", content.len()).into_bytes();
+                        response.extend(&content);
+                        response
+                    }
+                };
+                let _ = stream.write_all(&response);
+            }
+        });
+        return (url, server);
+    }
+
+    #[test]
+    fn test_download_segmented() {
+        let download_manager = DownloadManager::for_test();
+        let (tx, _rx) = mpsc::channel();
+        let ui = UserInterface::new(tx);
+        let temp_dir = TempDir::new().unwrap();
+        let segments_path = temp_dir.path().join("jvm.segments");
+        let content: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let received = AtomicUsize::new(0);
+        let report_progress = |progress: usize| { received.fetch_add(progress, Ordering::SeqCst); };
+
+        let (url, server) = serve(content.clone(), 4, true);
+        let mut file = download_manager.download_segmented(&url, 1000, &segments_path, 4, &report_progress, &ui).unwrap().unwrap();
+        server.join().unwrap();
+        let mut downloaded = Vec::new();
+        file.read_to_end(&mut downloaded).unwrap();
+        assert_eq!(content, downloaded);
+        assert_eq!(1000, received.load(Ordering::SeqCst));
+
+        // server without range support
+        let (url, server) = serve(content.clone(), 1, false);
+        assert!(download_manager.download_segmented(&url, 1000, &segments_path, 4, &report_progress, &ui).unwrap().is_none());
+        server.join().unwrap();
+
+        // size differs from the descriptor
+        let (url, server) = serve(content, 1, true);
+        assert!(download_manager.download_segmented(&url, 2000, &segments_path, 4, &report_progress, &ui).unwrap().is_none());
+        server.join().unwrap();
+    }
+
+    /// Serves the responses (created for the base URL of the server) by request path until the test ends, unknown
+    /// paths get a 404
+    fn serve_files<F: FnOnce(&str) -> HashMap<String, Vec<u8>>>(responses: F) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let responses = responses(&url);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = [0u8; 1024];
+                let length = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..length]).to_string();
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+                let _ = match responses.get(path) {
+                    Some(response) => stream.write_all(response),
+                    None => stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+                };
+            }
+        });
+        return url;
+    }
+
+    /// Response with the given content, announcing `length` bytes to simulate an interrupted transfer
+    fn response(content: &[u8], length: usize) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", length).into_bytes();
+        response.extend(content);
+        return response;
+    }
+
+    #[test]
+    fn test_download_and_store() {
+        let download_manager = DownloadManager::for_test();
+        let (tx, _rx) = mpsc::channel();
+        let ui = UserInterface::new(tx);
+
+        let create_archive = |content: &str| {
+            let mut builder = tar::Builder::new(Vec::new());
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, "bin/java", content.as_bytes()).unwrap();
+            return zstd::encode_all(builder.into_inner().unwrap().as_slice(), 0).unwrap();
+        };
+        let archive = create_archive("java");
+        let archive_checksum = blake3::hash(&archive).to_hex().to_string();
+        let java_checksum = blake3::hash(b"java").to_hex().to_string();
+        let jvm_checksum = combine_checksums(&BTreeMap::from([(String::from("bin/java"), java_checksum)]));
+        let test_checksum = "4878ca0425c739fa427f7eda20fe845f6b2e46ba5fe2a14df5b1e32f50603215";
+
+        let base_url = serve_files(|base_url| {
+            let descriptor = format!(r#"
+                name = "app"
+                version = "1.0"
+
+                [jvm]
+                path = "jvm/bin"
+                library = "server/jvm.dll"
+                main = "app/Main"
+                options = []
+
+                [[component]]
+                url = "{base_url}/jvm.tar.zstd"
+                download_size = {}
+                download_checksum = "{archive_checksum}"
+                size = 4
+                checksum = "{jvm_checksum}"
+                path = "jvm/"
+
+                [[component]]
+                url = "{base_url}/app.jar"
+                size = 4
+                checksum = "{test_checksum}"
+                path = "lib/app.jar"
+            "#, archive.len());
+            let mut responses = HashMap::new();
+            responses.insert(String::from("/app.toml"), response(descriptor.as_bytes(), descriptor.len()));
+            responses.insert(String::from("/app.jar"), response(b"test", 4));
+            responses.insert(String::from("/jvm.tar.zstd"), response(&archive, archive.len()));
+            let tampered = create_archive("evil");
+            responses.insert(String::from("/tampered.tar.zstd"), response(&tampered, tampered.len()));
+            responses.insert(String::from("/truncated.jar"), response(b"te", 4));
+            responses.insert(String::from("/modified.jar"), response(b"TEST", 4));
+            return responses;
+        });
+
+        // the whole pipeline: descriptor, download and validation
+        let temp_dir = TempDir::new().unwrap();
+        let installation = InstallationManager::with_root(temp_dir.path());
+        let content = download_manager.download_and_get(&format!("{}/app.toml", base_url)).unwrap();
+        let descriptor = ApplicationDescriptor::parse(&content, "app", None).unwrap();
+        download_manager.download_and_store(&descriptor.components, &installation, &ui).unwrap();
+        assert_eq!("java", fs::read_to_string(temp_dir.path().join("jvm/bin/java")).unwrap());
+        assert_eq!("test", fs::read_to_string(temp_dir.path().join("lib/app.jar")).unwrap());
+        for result in installation.check_components(&descriptor.components) {
+            assert!(matches!(result, OkLocked(_)));
+        }
+
+        let component = |name: &str| ApplicationComponent {
+            url: format!("{}/{}", base_url, name),
+            ..ApplicationComponent::for_test(&format!("lib/{}", name), 4, test_checksum)
+        };
+
+        let error = download_manager.download_and_store(&vec![component("missing.jar")], &installation, &ui).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::HttpStatusError(404, _)));
+
+        let truncated = vec![ApplicationComponent { download_size: Some(4), ..component("truncated.jar") }];
+        let error = download_manager.download_and_store(&truncated, &installation, &ui).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::DownloadError(_)));
+        assert!(matches!(installation.check_component(truncated[0].clone()), NotOk(_, CheckFailure::Size { expected: 4, actual: 2 })));
+
+        // the download succeeds, but the validation detects the wrong content
+        let modified = vec![component("modified.jar")];
+        download_manager.download_and_store(&modified, &installation, &ui).unwrap();
+        assert!(matches!(installation.check_component(modified[0].clone()), NotOk(_, CheckFailure::Checksum { .. })));
+
+        // a tampered archive is detected before anything is unpacked
+        let tampered = vec![ApplicationComponent {
+            path: String::from("tampered/"),
+            url: format!("{}/tampered.tar.zstd", base_url),
+            download_size: None,
+            download_checksum: Some(archive_checksum.clone()),
+            ..descriptor.components[0].clone()
+        }];
+        let error = download_manager.download_and_store(&tampered, &installation, &ui).unwrap_err();
+        assert!(error.to_string().contains("does not match"));
+        assert!(!temp_dir.path().join("tampered").exists());
+        assert!(!temp_dir.path().join("tampered.partial").exists());
+        assert!(!temp_dir.path().join("tampered.download").exists());
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(Some((0, 499, 1234)), DownloadManager::parse_content_range("bytes 0-499/1234"));
+        assert_eq!(None, DownloadManager::parse_content_range("bytes */1234"));
+        assert_eq!(None, DownloadManager::parse_content_range("0-499/1234"));
+    }
 }
\ No newline at end of file
@@ -16,6 +16,16 @@ error_chain!{
             description("download error")
             display("Error while downloading application components: {:}", msg)
         }
+        /// the server was not reachable or the connection broke off, retrying the download may succeed
+        NetworkError(msg: String) {
+            description("network error")
+            display("Error while downloading application components: {:}", msg)
+        }
+        /// the server was reachable, but answered with an error status (e.g. 404)
+        HttpStatusError(status: u16, msg: String) {
+            description("HTTP status error")
+            display("Error while downloading application components: {:}", msg)
+        }
         StorageError(msg: String) {
             description("storage error")
             display("Error while storing application components: {:}", msg)
@@ -28,9 +38,46 @@ error_chain!{
             description("splash error")
             display("Error while showing splash screen: {:}", msg)
         }
+        PostInstallError(msg: String) {
+            description("post-install error")
+            display("Error while running post-install command: {:}", msg)
+        }
         JavaExecutionError(msg: String) {
             description("Java execution error")
             display("Error while executing Java: {:}", msg)
         }
+        Cancelled {
+            description("cancelled")
+            display("Cancelled by user")
+        }
+    }
+}
+impl ErrorKind {
+    /// Returns true for errors which may not occur again if the launch is retried (e.g. network failures),
+    /// as opposed to permanent errors like invalid signatures or descriptors
+    pub fn is_transient(&self) -> bool {
+        return match self {
+            ErrorKind::NetworkError(_) => true,
+            // timeouts, rate limits and server errors
+            ErrorKind::HttpStatusError(status, _) => *status == 408 || *status == 429 || *status >= 500,
+            _ => false
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::ErrorKind;
+
+    #[test]
+    fn test_is_transient() {
+        assert!(ErrorKind::NetworkError(String::from("timeout")).is_transient());
+        assert!(ErrorKind::HttpStatusError(503, String::from("unavailable")).is_transient());
+        assert!(ErrorKind::HttpStatusError(429, String::from("too many requests")).is_transient());
+        assert!(!ErrorKind::HttpStatusError(404, String::from("not found")).is_transient());
+        assert!(!ErrorKind::DownloadError(String::from("not a zstd compressed tar archive")).is_transient());
+        assert!(!ErrorKind::SignatureError(String::from("invalid")).is_transient());
+        assert!(!ErrorKind::InvalidDescriptor(String::from("invalid")).is_transient());
+        assert!(!ErrorKind::Cancelled.is_transient());
     }
-}
\ No newline at end of file
+}
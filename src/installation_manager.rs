@@ -2,54 +2,242 @@ use std::collections::BTreeMap;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 extern crate dirs;
 
+use std::fmt;
 use std::fs;
-use std::fs::File;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
 use std::io::Read;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
 use blake3::Hasher;
 use log::*;
 
+use crate::clock::{Clock, SystemClock};
 use crate::errors::*;
-use crate::descriptor::ApplicationComponent;
+use crate::descriptor::{ApplicationComponent, ChecksumAlgorithm};
 use crate::descriptor::ApplicationDescriptor;
+use crate::download_manager::HttpCacheInfo;
 use walkdir::WalkDir;
-use cluFlock::{FlockLock, SharedFlock, ExclusiveFlock};
+use cluFlock::{FlockError, FlockLock, SharedFlock, ExclusiveFlock};
 use rayon::prelude::IntoParallelIterator;
 use crate::installation_manager::CheckResult::{NotOk, OkLocked};
 
 const DESCRIPTOR_FILE_NAME: &str = "app.toml";
+const DESCRIPTOR_CACHE_FILE_NAME: &str = "app.toml.cache";
+const DESCRIPTOR_CHECKSUM_FILE_NAME: &str = "app.toml.blake3";
 const LOG_FILE_NAME: &str = "launcher.log";
+const SPLASH_POSITION_FILE_NAME: &str = "splash.position";
 const BACKUP_DIR: &str = ".launcher.backup";
+/// the backup directory is renamed before it is removed, so that an interrupted removal cannot restore only some components
+const PURGED_BACKUP_DIR: &str = ".launcher.backup.purged";
+const INSTALLATION_LOCK_FILE_NAME: &str = ".launcher.lock";
+/// created and removed again to check if the installation can be updated
+const WRITE_TEST_FILE_NAME: &str = ".launcher.write_test";
+/// contains the checksum of the installation the post-install command succeeded for
+const POST_INSTALL_FILE_NAME: &str = ".launcher.post_install";
+/// contains the checksum of the installation and the time (seconds since epoch) of its last full validation
+const VERIFIED_FILE_NAME: &str = ".launcher.verified";
+const SHARED_DIR_ENV: &str = "NATIVESTART_SHARED_DIR";
+const LOCK_TIMEOUT_ENV: &str = "NATIVESTART_LOCK_TIMEOUT";
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
 
 pub struct InstallationManager {
     root_dir: PathBuf,
+    /// per user directory for the log file and splash position, equal to root_dir unless the installation is shared
+    user_dir: PathBuf,
+    /// maximum time to wait for a lock held by another process
+    lock_timeout: Duration,
+    clock: Arc<dyn Clock>,
 }
 
 pub enum CheckResult {
     OkLocked(Vec<FlockLock<File>>),
-    NotOk(ApplicationComponent)
+    NotOk(ApplicationComponent, CheckFailure)
+}
+
+/// Single check of an installed component
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Validator {
+    Exists,
+    Size,
+    /// expensive for large components, detects all modifications
+    Checksum,
+    Mode,
+}
+
+/// Detects all modifications of the installed files
+pub const FULL_VALIDATION: [Validator; 4] = [Validator::Exists, Validator::Size, Validator::Checksum, Validator::Mode];
+/// Skips the checksum for a faster launch, only detects missing files and changed sizes
+pub const QUICK_VALIDATION: [Validator; 3] = [Validator::Exists, Validator::Size, Validator::Mode];
+
+/// Reason why an installed component does not match the descriptor
+#[derive(Debug, PartialEq)]
+pub enum CheckFailure {
+    Missing,
+    Size { expected: u64, actual: u64 },
+    Checksum { expected: String, actual: String },
+    Mode { expected: u32 },
+    /// file vanished or could not be locked, e.g. during a concurrent update
+    Lock(String),
+}
+
+impl fmt::Display for CheckFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            CheckFailure::Missing => write!(f, "file missing"),
+            CheckFailure::Size { expected, actual } => write!(f, "size mismatch (expected {}, actual {})", expected, actual),
+            CheckFailure::Checksum { expected, actual } => write!(f, "checksum mismatch (expected {}, actual {})", expected, actual),
+            CheckFailure::Mode { expected } => write!(f, "mode mismatch (expected {:o})", expected),
+            CheckFailure::Lock(message) => write!(f, "{}", message),
+        };
+    }
+}
+
+/// Checksum of a list of paths and their checksums, used for directories and the whole installation
+pub fn combine_checksums(checksums: &BTreeMap<String, String>) -> String {
+    return combine_checksums_with(checksums, ChecksumAlgorithm::Blake3);
+}
+
+fn combine_checksums_with(checksums: &BTreeMap<String, String>, algorithm: ChecksumAlgorithm) -> String {
+    let mut hasher = ComponentHasher::new(algorithm);
+    for (path, hash) in checksums {
+        hasher.update(path.as_bytes());
+        hasher.update(b"\t");
+        hasher.update(hash.as_bytes());
+        hasher.update(b"\n");
+    }
+    return hasher.finalize();
+}
+
+/// Checksum of the content of the reader, e.g. of a downloaded archive
+pub fn hash_reader<R: Read>(reader: R, algorithm: ChecksumAlgorithm) -> std::io::Result<String> {
+    let mut hasher = ComponentHasher::new(algorithm);
+    hasher.update_reader(reader)?;
+    return Ok(hasher.finalize());
+}
+
+/// Hasher for the checksum algorithm of a component
+enum ComponentHasher {
+    Blake3(Hasher),
+    #[cfg(feature = "sha256")]
+    Sha256(ring::digest::Context),
+}
+
+impl ComponentHasher {
+    fn new(algorithm: ChecksumAlgorithm) -> ComponentHasher {
+        return match algorithm {
+            #[cfg(feature = "sha256")]
+            ChecksumAlgorithm::Sha256 => ComponentHasher::Sha256(ring::digest::Context::new(&ring::digest::SHA256)),
+            // unsupported algorithms are rejected when parsing the descriptor
+            _ => ComponentHasher::Blake3(Hasher::new()),
+        };
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            ComponentHasher::Blake3(hasher) => { hasher.update(data); },
+            #[cfg(feature = "sha256")]
+            ComponentHasher::Sha256(context) => context.update(data),
+        }
+    }
+
+    fn update_reader<R: Read>(&mut self, reader: R) -> std::io::Result<()> {
+        match self {
+            ComponentHasher::Blake3(hasher) => { hasher.update_reader(reader)?; },
+            #[cfg(feature = "sha256")]
+            ComponentHasher::Sha256(context) => {
+                let mut reader = reader;
+                let mut buffer = [0u8; 65536];
+                loop {
+                    let count = reader.read(&mut buffer)?;
+                    if count == 0 {
+                        break;
+                    }
+                    context.update(&buffer[..count]);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Lowercase hex string of the checksum
+    fn finalize(self) -> String {
+        return match self {
+            ComponentHasher::Blake3(hasher) => String::from(hasher.finalize().to_hex().as_str()),
+            #[cfg(feature = "sha256")]
+            ComponentHasher::Sha256(context) => context.finish().as_ref().iter().map(|byte| format!("{:02x}", byte)).collect(),
+        };
+    }
 }
 
 impl InstallationManager {
     pub fn new(app_id: &'static str) -> Result<InstallationManager> {
-        let mut cache_path = dirs::cache_dir()
+        let installation_manager = InstallationManager::locate(app_id)?;
+        fs::create_dir_all(&installation_manager.user_dir)
+            .chain_err(|| ErrorKind::StorageError(format!("Could not create installation directory {:?}", &installation_manager.user_dir)))?;
+        if installation_manager.root_dir != installation_manager.user_dir {
+            fs::create_dir_all(&installation_manager.root_dir)
+                .chain_err(|| ErrorKind::StorageError(format!("Could not create shared installation directory {:?}", &installation_manager.root_dir)))?;
+        }
+        return Ok(installation_manager);
+    }
+
+    /// Determines the directories of the installation without creating them, e.g. to report the status of an
+    /// application which may not be installed
+    pub fn locate(app_id: &'static str) -> Result<InstallationManager> {
+        return InstallationManager::locate_in(app_id, dirs::cache_dir(), env::var_os(SHARED_DIR_ENV));
+    }
+
+    fn locate_in(app_id: &'static str, cache_dir: Option<PathBuf>, shared_dir: Option<OsString>) -> Result<InstallationManager> {
+        let mut cache_path = cache_dir
             .chain_err(|| ErrorKind::StorageError(format!("Could not determine cache directory")))?;
         cache_path.push(app_id);
-        fs::create_dir_all(&cache_path)
-            .chain_err(|| ErrorKind::StorageError(format!("Could not create installation directory {:?}", &cache_path)))?;
+
+        // machine wide installation shared by all users, e.g. /opt/nativestart or C:\ProgramData\nativestart
+        let root_dir = match shared_dir {
+            Some(shared_dir) if !shared_dir.is_empty() => PathBuf::from(shared_dir).join(app_id),
+            _ => cache_path.clone()
+        };
 
         return Ok(InstallationManager {
-            root_dir: cache_path,
+            root_dir,
+            user_dir: cache_path,
+            lock_timeout: InstallationManager::lock_timeout(env::var(LOCK_TIMEOUT_ENV).ok()),
+            clock: Arc::new(SystemClock),
         });
     }
 
+    /// Installation in the given directory, e.g. a temporary directory of a test
+    #[cfg(test)]
+    pub fn with_root(root_dir: &Path) -> InstallationManager {
+        return InstallationManager {
+            root_dir: PathBuf::from(root_dir),
+            user_dir: PathBuf::from(root_dir),
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            clock: Arc::new(SystemClock),
+        };
+    }
+
     pub fn get_log_file(&self) -> Result<File> {
-        let path = self.get_installation_root().join(LOG_FILE_NAME);
+        let path = self.user_dir.join(LOG_FILE_NAME);
         return File::create(&path)
             .chain_err(|| ErrorKind::StorageError(format!("Could not create log file {:?}", &path)));
     }
 
+    pub fn get_splash_position_file(&self) -> PathBuf {
+        return self.user_dir.join(SPLASH_POSITION_FILE_NAME);
+    }
+
+    pub fn get_user_dir(&self) -> PathBuf {
+        return self.user_dir.clone();
+    }
+
     pub fn is_descriptor_locked(&self) -> Result<bool> {
         let path = self.path(DESCRIPTOR_FILE_NAME);
         if !path.exists() {
@@ -67,28 +255,115 @@ impl InstallationManager {
             .chain_err(|| ErrorKind::StorageError(format!("Could not create descriptor file {:?}", &path)))?;
         file.write_all(&descriptor.as_bytes())
             .chain_err(|| ErrorKind::StorageError(format!("Could not write descriptor file {:?}", &path)))?;
+
+        // moved to the backup together with the descriptor, so a restored descriptor still matches its checksum
+        let checksum_path = self.path_for_write(DESCRIPTOR_CHECKSUM_FILE_NAME)?;
+        fs::write(&checksum_path, blake3::hash(descriptor.as_bytes()).to_hex().as_str())
+            .chain_err(|| ErrorKind::StorageError(format!("Could not write descriptor checksum file {:?}", &checksum_path)))?;
         return Ok(());
     }
 
+    /// HTTP validators of the stored descriptor. Empty if there is no stored descriptor.
+    pub fn get_descriptor_cache_info(&self) -> HttpCacheInfo {
+        if self.get_descriptor().is_none() {
+            return HttpCacheInfo::default();
+        }
+        return fs::read_to_string(self.path(DESCRIPTOR_CACHE_FILE_NAME)).ok()
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default();
+    }
+
+    pub fn store_descriptor_cache_info(&self, cache_info: &HttpCacheInfo) -> Result<()> {
+        let path = self.path(DESCRIPTOR_CACHE_FILE_NAME);
+        let content = toml::to_string(cache_info)
+            .chain_err(|| ErrorKind::StorageError(format!("Could not serialize descriptor cache information")))?;
+        fs::write(&path, content)
+            .chain_err(|| ErrorKind::StorageError(format!("Could not write descriptor cache file {:?}", &path)))?;
+        return Ok(());
+    }
+
+    /// Exclusively locks the installation while it gets checked and updated. Concurrent launches (e.g. by several
+    /// users of a shared installation) wait until the first one is done and then only validate the installed files.
+    pub fn lock_for_update(&self) -> Result<FlockLock<File>> {
+        let path = self.path(INSTALLATION_LOCK_FILE_NAME);
+        // locking does not require write access, so the lock created by another user of a shared installation can be used
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(_) => OpenOptions::new().create(true).write(true).open(&path)
+                .chain_err(|| ErrorKind::StorageError(format!("Could not open installation lock {:?}", &path)))?
+        };
+        return self.wait_lock(file, &path, ExclusiveFlock::try_lock);
+    }
+
+    /// Checks if the current user can update the installation. A shared installation may be read-only for all users
+    /// except the one who installed it, the others can only validate and start it.
+    pub fn is_writable(&self) -> bool {
+        let path = self.path(WRITE_TEST_FILE_NAME);
+        return match OpenOptions::new().create(true).write(true).open(&path) {
+            Ok(_) => {
+                let _ = fs::remove_file(&path);
+                true
+            }
+            Err(_) => false
+        };
+    }
+
     pub fn lock_descriptor(&self) -> Result<FlockLock<File>> {
         let path = self.path(DESCRIPTOR_FILE_NAME);
-        return Ok(SharedFlock::wait_lock(File::open(path)?).unwrap());
+        return self.lock_file(&path);
     }
 
+    /// Returns the stored descriptor. A corrupted descriptor is removed, so that it gets downloaded again.
     pub fn get_descriptor(&self) -> Option<String> {
         self.restore_trash(DESCRIPTOR_FILE_NAME).unwrap();
+        self.restore_trash(DESCRIPTOR_CHECKSUM_FILE_NAME).unwrap();
         let path = self.path(DESCRIPTOR_FILE_NAME);
 
-        return match File::open(&path) {
+        let contents = match File::open(&path) {
             Ok(mut file) => {
                 let mut contents = String::new();
                 match file.read_to_string(&mut contents) {
-                    Ok(_) => Option::Some(contents),
-                    Err(_) => Option::None
+                    Ok(_) => contents,
+                    Err(_) => return Option::None
                 }
             }
-            Err(_) => Option::None
+            Err(_) => return Option::None
         };
+        if !self.is_descriptor_intact(&contents) {
+            warn!("Stored application descriptor is corrupted, discarding it");
+            let _ = fs::remove_file(&path);
+            let _ = fs::remove_file(self.path(DESCRIPTOR_CHECKSUM_FILE_NAME));
+            let _ = fs::remove_file(self.path(DESCRIPTOR_CACHE_FILE_NAME));
+            return Option::None;
+        }
+        return Option::Some(contents);
+    }
+
+    /// Returns the stored descriptor without restoring backups or removing a corrupted descriptor, e.g. while
+    /// the installation may be updated by another launch
+    pub fn read_descriptor(&self) -> Option<String> {
+        let contents = fs::read_to_string(self.path(DESCRIPTOR_FILE_NAME)).ok()?;
+        if !self.is_descriptor_intact(&contents) {
+            return None;
+        }
+        return Some(contents);
+    }
+
+    fn is_descriptor_intact(&self, contents: &str) -> bool {
+        // descriptors stored by older versions do not have a checksum
+        return match fs::read_to_string(self.path(DESCRIPTOR_CHECKSUM_FILE_NAME)) {
+            Ok(checksum) => checksum.trim() == blake3::hash(contents.as_bytes()).to_hex().as_str(),
+            Err(_) => true
+        };
+    }
+
+    /// Returns name and version of the installed application from the stored descriptor. The descriptor is not
+    /// validated (e.g. its signature), so the result is informational only.
+    pub fn installed_version(&self) -> Option<(String, String)> {
+        let descriptor: toml::Table = toml::from_str(&self.read_descriptor()?).ok()?;
+        let name = descriptor.get("name")?.as_str()?;
+        let version = descriptor.get("version")?.as_str()?;
+        return Some((String::from(name), String::from(version)));
     }
 
     pub fn create_unmanaged(&self, descriptor: &ApplicationDescriptor) -> Result<()> {
@@ -97,51 +372,80 @@ impl InstallationManager {
             fs::create_dir_all(&path)
                 .chain_err(|| ErrorKind::StorageError(format!("Could not create directory {:?}", &path)))?;
         }
+        if let Some(working_dir) = &descriptor.jvm_params.working_dir {
+            let path = self.path(working_dir);
+            fs::create_dir_all(&path)
+                .chain_err(|| ErrorKind::StorageError(format!("Could not create working directory {:?}", &path)))?;
+        }
         Ok(())
     }
 
     pub fn delete_unused_files(&self, descriptor: &ApplicationDescriptor) -> Result<()> {
+        // create cache paths if they do not yet exist
+        for component in &descriptor.components {
+            if let Some(cache_path) = &component.cache_path {
+                let path = self.path(cache_path);
+                if !path.exists() {
+                    fs::create_dir_all(&path)?;
+                }
+            }
+        }
+
+        // files still in use (e.g. by a running JVM on Windows) cannot be deleted, they stay unused and are deleted
+        // by one of the next launches
+        for entry_path in self.get_unused_files(descriptor)? {
+            if entry_path.exists() {
+                let removed = if entry_path.is_file() {
+                    fs::remove_file(&entry_path)
+                } else {
+                    fs::remove_dir_all(&entry_path)
+                };
+                if let Err(e) = removed {
+                    warn!("Could not remove unused {:?}, retrying with the next launch: {}", &entry_path, e);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Files and directories in the installation root which are not part of the application
+    pub fn get_unused_files(&self, descriptor: &ApplicationDescriptor) -> Result<Vec<PathBuf>> {
         let mut component_paths: Vec<PathBuf> = descriptor.components
             .iter()
             .map(|component| self.path(component))
             .collect();
 
-        // add synthetic component path for descriptor and log file to ensure that the file will not be deleted
+        // add synthetic component path for descriptor, descriptor cache info and checksum, installation lock, post-install state, log file and splash position to ensure that the file will not be deleted
         component_paths.push(self.path(DESCRIPTOR_FILE_NAME));
+        component_paths.push(self.path(DESCRIPTOR_CACHE_FILE_NAME));
+        component_paths.push(self.path(DESCRIPTOR_CHECKSUM_FILE_NAME));
+        component_paths.push(self.path(INSTALLATION_LOCK_FILE_NAME));
+        component_paths.push(self.path(POST_INSTALL_FILE_NAME));
+        component_paths.push(self.path(VERIFIED_FILE_NAME));
         component_paths.push(self.path(LOG_FILE_NAME));
+        component_paths.push(self.path(SPLASH_POSITION_FILE_NAME));
         
         // manually add component path for the splash component due it is not included in the main components list
-        component_paths.push(self.path(&descriptor.splash));
+        if let Some(splash) = &descriptor.splash {
+            component_paths.push(self.path(splash));
+        }
 
         // add unmanaged paths (like plugins or other user managed directories)
         for path in descriptor.unmanaged_paths.as_ref().unwrap_or(&vec![]) {
             component_paths.push(self.path(path));
         }
-        // add cache paths and create them if they do not yet exist
+        // the working directory contains data of the application
+        if let Some(working_dir) = &descriptor.jvm_params.working_dir {
+            component_paths.push(self.path(working_dir));
+        }
+        // add cache paths
         for component in &descriptor.components {
-            if component.cache_path.is_some() {
-                let path = self.path(component.cache_path.as_ref().unwrap());
-                if !path.exists() {
-                    fs::create_dir_all(&path)?;
-                }
-                component_paths.push(path);
+            if let Some(cache_path) = &component.cache_path {
+                component_paths.push(self.path(cache_path));
             }
         }
 
-        let entries_to_delete: Vec<PathBuf> = self.get_paths_to_delete(self.get_installation_root().as_path(), &component_paths)?;
-
-        for entry_path in entries_to_delete {
-            if entry_path.exists() {
-                if entry_path.is_file() {
-                    fs::remove_file(&entry_path)
-                        .chain_err(|| ErrorKind::StorageError(format!("Could not remove unused file {:?}", &entry_path)))?;
-                } else {
-                    fs::remove_dir_all(&entry_path)
-                        .chain_err(|| ErrorKind::StorageError(format!("Could not remove unused directory {:?}", &entry_path)))?;
-                }
-            }
-        }
-        return Ok(());
+        return self.get_paths_to_delete(self.get_installation_root().as_path(), &component_paths);
     }
 
     fn get_paths_to_delete(&self, root: &Path, component_paths: &Vec<PathBuf>) -> Result<Vec<PathBuf>> {
@@ -177,60 +481,214 @@ impl InstallationManager {
         return Ok(entries_to_delete);
     }
 
+    /// Runs the post-install command of the descriptor in the installation root, once per installed version
+    pub fn run_post_install(&self, descriptor: &ApplicationDescriptor) -> Result<()> {
+        let command = match descriptor.post_install.as_ref().and_then(|command| command.split_first()) {
+            Some(command) => command,
+            None => return Ok(())
+        };
+        let marker_path = self.path(POST_INSTALL_FILE_NAME);
+        let checksum = descriptor.checksum();
+        if fs::read_to_string(&marker_path).is_ok_and(|done| done == checksum) {
+            debug!("Post-install command already done for this version");
+            return Ok(());
+        }
+
+        info!("Running post-install command {:?}", descriptor.post_install);
+        let status = Command::new(self.path(command.0))
+            .args(command.1)
+            .current_dir(self.get_installation_root())
+            .status()
+            .chain_err(|| ErrorKind::PostInstallError(format!("Could not start {}", command.0)))?;
+        if !status.success() {
+            bail!(ErrorKind::PostInstallError(format!("{} failed ({})", command.0, status)));
+        }
+        fs::write(&marker_path, checksum)
+            .chain_err(|| ErrorKind::StorageError(format!("Could not write {:?}", &marker_path)))?;
+        return Ok(());
+    }
+
+    /// Records that the installation of the given descriptor passed the full validation
+    pub fn store_verified(&self, descriptor: &ApplicationDescriptor) -> Result<()> {
+        let now = self.clock.system_time().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs());
+        let path = self.path(VERIFIED_FILE_NAME);
+        fs::write(&path, format!("{} {}", descriptor.checksum(), now))
+            .chain_err(|| ErrorKind::StorageError(format!("Could not write {:?}", &path)))?;
+        return Ok(());
+    }
+
+    /// Checks if the installation of the given descriptor passed the full validation within the given time
+    pub fn is_recently_verified(&self, descriptor: &ApplicationDescriptor, max_age: Duration) -> bool {
+        let content = match fs::read_to_string(self.path(VERIFIED_FILE_NAME)) {
+            Ok(content) => content,
+            Err(_) => return false
+        };
+        let (checksum, verified) = match content.trim().split_once(' ') {
+            Some((checksum, verified)) => (checksum, verified.parse::<u64>().unwrap_or(0)),
+            None => return false
+        };
+        let now = self.clock.system_time().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs());
+        // a time in the future (e.g. after changing the clock) is not trusted
+        return checksum == descriptor.checksum() && verified <= now && now - verified < max_age.as_secs();
+    }
+
     pub fn restore_backup(&self, components: &Vec<ApplicationComponent>) {
         for component in components {
             self.restore_trash(&component).unwrap();
         }
     }
 
+    /// Removes the previous versions of updated components, must only be called once the installation is verified
+    pub fn purge_backup(&self) -> Result<()> {
+        let path = self.path(BACKUP_DIR);
+        let purged_path = self.path(PURGED_BACKUP_DIR);
+        if path.exists() {
+            info!("Removing backup of previous version");
+            // the rename commits the update, a remaining purged directory is only removed
+            if purged_path.exists() {
+                fs::remove_dir_all(&purged_path)
+                    .chain_err(|| ErrorKind::StorageError(format!("Could not remove backup directory {:?}", &purged_path)))?;
+            }
+            fs::rename(&path, &purged_path)
+                .chain_err(|| ErrorKind::StorageError(format!("Could not remove backup directory {:?}", &path)))?;
+        }
+        if purged_path.exists() {
+            fs::remove_dir_all(&purged_path)
+                .chain_err(|| ErrorKind::StorageError(format!("Could not remove backup directory {:?}", &purged_path)))?;
+        }
+        return Ok(());
+    }
+
+    /// Removes partially written components and restores their previous version if there is one
+    pub fn rollback(&self, components: &Vec<ApplicationComponent>) -> Result<()> {
+        for component in components {
+            let path = self.path(component);
+            if path.is_dir() {
+                fs::remove_dir_all(&path)
+                    .chain_err(|| ErrorKind::StorageError(format!("Could not remove directory {:?}", &path)))?;
+            } else if path.exists() {
+                fs::remove_file(&path)
+                    .chain_err(|| ErrorKind::StorageError(format!("Could not remove file {:?}", &path)))?;
+            }
+            self.restore_trash(component)?;
+        }
+        return Ok(());
+    }
+
     pub fn check_component(&self, component: ApplicationComponent) -> CheckResult {
+        return self.check_component_with(component, &FULL_VALIDATION);
+    }
+
+    /// Runs the validators in the given order and stops at the first failure. Valid components are locked.
+    pub fn check_component_with(&self, component: ApplicationComponent, validators: &[Validator]) -> CheckResult {
         info!("Checking {}", component.path);
         let path = self.path(&component);
 
-        if !path.exists() {
-            NotOk(component)
-        } else if self.size(&path) != component.size {
-            info!("The size of {} is {}, but should be {}", &component.path, self.size(&path), &component.size);
-            NotOk(component)
-        } else {
-            let files = self.lock(&path);
-            let hash = if path.is_dir() {self.hash_dir(&path, &files)} else {self.hash_file(&path)};
-            let hash_match = hash.as_str().eq(&component.checksum);
-            if !hash_match {
-                info!("The hash of {} is {}, but should be {}", &component.path, hash, &component.checksum);
-                self.unlock(files);
-                NotOk(component)
-            } else {
-                let mut locks: Vec<FlockLock<File>> = Vec::new();
-                for file in files {
-                    locks.push(file.1);
+        let mut files: Option<Vec<(PathBuf, FlockLock<File>)>> = None;
+        for validator in validators {
+            let failure = match validator {
+                Validator::Exists if !path.exists() => Some(CheckFailure::Missing),
+                Validator::Exists => None,
+                Validator::Size => {
+                    let size = self.size(&path);
+                    if size != component.size { Some(CheckFailure::Size { expected: component.size, actual: size }) } else { None }
+                }
+                Validator::Checksum => {
+                    // lock before hashing, so the files cannot be replaced after the check
+                    let locked = match files.take().map_or_else(|| self.lock(&path), Ok) {
+                        Ok(locked) => locked,
+                        Err(e) => return NotOk(component, CheckFailure::Lock(e.to_string()))
+                    };
+                    let algorithm = component.checksum_algorithm();
+                    let hash = if path.is_dir() {self.hash_dir(&path, &locked, algorithm)} else {self.hash_file(&path, algorithm)};
+                    let hash = match hash {
+                        Ok(hash) => hash,
+                        // removed between listing and hashing, e.g. by a third party
+                        Err(e) => {
+                            self.unlock(locked);
+                            return NotOk(component, CheckFailure::Lock(e.to_string()));
+                        }
+                    };
+                    files = Some(locked);
+                    if !hash.as_str().eq(&component.checksum) {
+                        Some(CheckFailure::Checksum { expected: component.checksum.clone(), actual: hash })
+                    } else { None }
+                }
+                Validator::Mode if !self.has_mode(&path, component.mode) => Some(CheckFailure::Mode { expected: component.mode.unwrap_or_default() }),
+                Validator::Mode => None,
+            };
+            if let Some(failure) = failure {
+                if let Some(files) = files {
+                    self.unlock(files);
                 }
-                OkLocked(locks)
+                return NotOk(component, failure);
             }
         }
+
+        let files = match files.map_or_else(|| self.lock(&path), Ok) {
+            Ok(files) => files,
+            Err(e) => return NotOk(component, CheckFailure::Lock(e.to_string()))
+        };
+        let mut locks: Vec<FlockLock<File>> = Vec::new();
+        for file in files {
+            locks.push(file.1);
+        }
+        return OkLocked(locks);
     }
 
     pub fn check_components(&self, components: &Vec<ApplicationComponent>) -> Vec<CheckResult> {
+        return self.check_components_with(components, &FULL_VALIDATION);
+    }
+
+    pub fn check_components_with(&self, components: &Vec<ApplicationComponent>, validators: &[Validator]) -> Vec<CheckResult> {
         components.into_par_iter().cloned().map(|component| {
-            self.check_component(component)
+            self.check_component_with(component, validators)
+        }).collect()
+    }
+
+    /// Checks the components in parallel and reports the size of the checked components and the total size after each component.
+    /// The check is aborted with the first error returned by `on_progress`, e.g. if the user cancelled it.
+    pub fn check_components_with_progress<F>(&self, components: &Vec<ApplicationComponent>, validators: &[Validator], on_progress: F) -> Result<Vec<CheckResult>>
+        where F: Fn(u64, u64) -> Result<()> + Sync {
+        let total_size: u64 = components.iter().map(|component| component.size).sum();
+        let checked_size = AtomicU64::new(0);
+        components.into_par_iter().cloned().map(|component| {
+            let size = component.size;
+            let result = self.check_component_with(component, validators);
+            on_progress(checked_size.fetch_add(size, Ordering::SeqCst) + size, total_size)?;
+            Ok(result)
         }).collect()
     }
 
     fn size(&self, file_path: &Path) -> u64 {
         if file_path.is_dir() {
-            WalkDir::new(file_path)
+            // gathering the metadata is the expensive part for large directories -> do it in parallel
+            let entries: Vec<walkdir::DirEntry> = WalkDir::new(file_path)
                 .follow_links(false)
                 .into_iter()
                 .filter_map(|entry| entry.ok())
-                .filter_map(|entry| entry.metadata().ok())
-                .filter(|metadata| metadata.is_file())
-                .fold(0, |acc, m| acc + m.len())
+                .collect();
+            entries.into_par_iter()
+                .map(|entry| self.file_size(entry.path()))
+                .sum()
         } else {
-            fs::metadata(file_path).and_then(|m| Ok(m.len())).unwrap_or(0)
+            self.file_size(file_path)
         }
     }
 
-    fn lock(&self, file_path: &Path) -> Vec<(PathBuf, FlockLock<File>)> {
+    /// Size of a file. Symbolic links count with the length of their target, as their target is hashed instead of
+    /// their content.
+    fn file_size(&self, file_path: &Path) -> u64 {
+        return match fs::symlink_metadata(file_path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => fs::read_link(file_path)
+                .map(|target| target.as_path().to_str().unwrap_or_default().len() as u64)
+                .unwrap_or(0),
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => 0
+        };
+    }
+
+    fn lock(&self, file_path: &Path) -> Result<Vec<(PathBuf, FlockLock<File>)>> {
         if file_path.is_dir() {
             WalkDir::new(file_path)
                 .into_iter()
@@ -239,53 +697,136 @@ impl InstallationManager {
                     Ok(metadata) => !metadata.is_dir(),
                     Err(_) => false
                 })
-                .map(|path|
-                         (path.clone().into_path(), SharedFlock::wait_lock(File::open(path.into_path()).unwrap()).unwrap()))
+                .map(|entry| {
+                    let path = entry.into_path();
+                    let lock = self.lock_file(&path)?;
+                    Ok((path, lock))
+                })
                 .collect()
         } else {
-            vec!((file_path.to_path_buf(), SharedFlock::wait_lock(File::open(file_path).unwrap()).unwrap()))
+            return Ok(vec!((file_path.to_path_buf(), self.lock_file(file_path)?)));
         }
     }
 
+    /// Fails if the file has been removed in the meantime (e.g. by a concurrent update)
+    fn lock_file(&self, file_path: &Path) -> Result<FlockLock<File>> {
+        let file = File::open(file_path)
+            .chain_err(|| ErrorKind::StorageError(format!("Could not open {:?}", file_path)))?;
+        return self.wait_lock(file, file_path, SharedFlock::try_lock);
+    }
+
+    /// Retries to lock the file until the lock timeout elapsed
+    fn wait_lock(&self, file: File, file_path: &Path,
+                 try_lock: fn(File) -> std::result::Result<FlockLock<File>, FlockError<File>>) -> Result<FlockLock<File>> {
+        let start = self.clock.now();
+        let mut file = file;
+        loop {
+            match try_lock(file) {
+                Ok(lock) => return Ok(lock),
+                Err(e) => file = e.into(),
+            }
+            if self.clock.elapsed(start) >= self.lock_timeout {
+                bail!(ErrorKind::StorageError(format!("Could not lock {:?} within {} seconds. Another instance of the application may still be running.",
+                    file_path, self.lock_timeout.as_secs())));
+            }
+            if self.clock.elapsed(start) < LOCK_RETRY_INTERVAL {
+                info!("Waiting for lock on {:?}, another instance of the application may be running", file_path);
+            }
+            self.clock.sleep(LOCK_RETRY_INTERVAL);
+        }
+    }
+
+    /// Parses the configured lock timeout in seconds, invalid values are ignored
+    fn lock_timeout(value: Option<String>) -> Duration {
+        return value.and_then(|value| value.trim().parse::<u64>().ok())
+            .map_or(DEFAULT_LOCK_TIMEOUT, Duration::from_secs);
+    }
+
     pub fn unlock(&self, files : Vec<(PathBuf, FlockLock<File>)>) {
         for file in files {
             file.1.unlock_no_err_result();
         }
     }
 
-    fn hash_dir(&self, file_path: &Path, files : &Vec<(PathBuf, FlockLock<File>)>) -> String {
-        let hash_vec : Vec<_> = files.par_iter().filter_map(|(file, _)| {
-            let hash = self.hash_file(file);
+    fn hash_dir(&self, file_path: &Path, files : &Vec<(PathBuf, FlockLock<File>)>, algorithm: ChecksumAlgorithm) -> Result<String> {
+        let hash_vec : Vec<_> = files.par_iter().map(|(file, _)| {
+            let hash = self.hash_file(file, algorithm)?;
             let path = String::from(file.strip_prefix(file_path).unwrap()
                 .to_str().unwrap()
                 .replace("\\", "/"));
-            Some((path, hash))
-        }).collect();
+            Ok((path, hash))
+        }).collect::<Result<_>>()?;
 
         let mut hashes = BTreeMap::new();
         for (path, hash) in hash_vec {
             hashes.insert(path, hash);
         }
-        let mut hasher = Hasher::new();
-        for (path, hash) in &hashes {
-            hasher.update(path.as_bytes());
-            hasher.update(b"\t");
-            hasher.update(hash.as_bytes());
-            hasher.update(b"\n");
+        return Ok(combine_checksums_with(&hashes, algorithm));
+    }
+
+    /// Combined checksum of all installed components, equal to `ApplicationDescriptor::checksum` for a valid
+    /// installation. Can be used by external tools to attest the installation.
+    pub fn installation_checksum(&self, descriptor: &ApplicationDescriptor) -> Result<String> {
+        let mut checksums = BTreeMap::new();
+        for component in descriptor.all_components() {
+            let path = self.path(component);
+            if !path.exists() {
+                bail!(ErrorKind::ValidationError(format!("{} is missing", component.path)));
+            }
+            let files = self.lock(&path)?;
+            let algorithm = component.checksum_algorithm();
+            let hash = if path.is_dir() {self.hash_dir(&path, &files, algorithm)} else {self.hash_file(&path, algorithm)};
+            self.unlock(files);
+            checksums.insert(component.path.clone(), hash?);
         }
-        String::from(hasher.finalize().to_hex().as_str())
+        return Ok(combine_checksums(&checksums));
+    }
+
+    #[cfg(unix)]
+    fn has_mode(&self, path: &Path, mode: Option<u32>) -> bool {
+        use std::os::unix::fs::PermissionsExt;
+        return match mode {
+            Some(mode) => fs::metadata(path).map_or(false, |metadata| metadata.permissions().mode() & 0o7777 == mode),
+            None => true
+        };
+    }
+
+    #[cfg(not(unix))]
+    fn has_mode(&self, _path: &Path, _mode: Option<u32>) -> bool {
+        return true;
+    }
+
+    /// Applies the permission bits defined for a component after it has been stored
+    #[cfg(unix)]
+    pub fn apply_mode(&self, component: &ApplicationComponent) -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        if let Some(mode) = component.mode {
+            let path = self.path(component);
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))
+                .chain_err(|| ErrorKind::StorageError(format!("Could not set mode of {:?}", &path)))?;
+        }
+        return Ok(());
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply_mode(&self, _component: &ApplicationComponent) -> Result<()> {
+        return Ok(());
     }
 
-    fn hash_file(&self, file_path: &Path) -> String {
+    /// Fails if the file has been removed in the meantime (e.g. after it was listed and locked)
+    fn hash_file(&self, file_path: &Path, algorithm: ChecksumAlgorithm) -> Result<String> {
         debug!("Hashing {:?}", file_path);
-        let mut hasher = Hasher::new();
+        let mut hasher = ComponentHasher::new(algorithm);
         match fs::read_link(file_path) {
             Ok(target) => hasher.update(target.as_path().to_str().unwrap().as_bytes()),
             Err(_e) => {
-                hasher.update_reader(File::open(file_path).unwrap()).unwrap()
+                let file = File::open(file_path)
+                    .chain_err(|| ErrorKind::StorageError(format!("Could not open {:?}", file_path)))?;
+                hasher.update_reader(file)
+                    .chain_err(|| ErrorKind::StorageError(format!("Could not read {:?}", file_path)))?;
             }
         };
-        String::from(hasher.finalize().to_hex().as_str())
+        return Ok(hasher.finalize());
     }
 
     pub fn unlock_files(&self, files: Vec<FlockLock<File>>) -> Result<()> {
@@ -366,12 +907,21 @@ impl InstallationManager {
 mod tests {
     use std::fs;
     use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use crate::clock::{Clock, ManualClock};
+    use crate::errors::{Error, ErrorKind};
 
-    use crate::installation_manager::{InstallationManager, DESCRIPTOR_FILE_NAME};
+    use crate::installation_manager::{InstallationManager, DEFAULT_LOCK_TIMEOUT, DESCRIPTOR_FILE_NAME};
+    use std::time::Duration;
     use std::fs::File;
     use std::io::{Write, Read};
     use tempfile::TempDir;
-    use crate::descriptor::ApplicationComponent;
+    use crate::descriptor::{ApplicationComponent, ApplicationDescriptor, ChecksumAlgorithm, TEST_DESCRIPTOR};
+    use crate::download_manager::HttpCacheInfo;
+    use crate::installation_manager::{CheckFailure, Validator, QUICK_VALIDATION};
+    use crate::installation_manager::CheckResult::{NotOk, OkLocked};
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
 
     #[test]
     fn test_size_hash_single_file() {
@@ -383,7 +933,16 @@ mod tests {
         temporary_file.write_all(b"test").unwrap();
 
         assert_eq!(4, installation.size(path.as_path()));
-        assert_eq!("4878ca0425c739fa427f7eda20fe845f6b2e46ba5fe2a14df5b1e32f50603215", installation.hash_file(path.as_path()));
+        assert_eq!("4878ca0425c739fa427f7eda20fe845f6b2e46ba5fe2a14df5b1e32f50603215", installation.hash_file(path.as_path(), ChecksumAlgorithm::Blake3).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "sha256")]
+    fn test_hash_sha256() {
+        let (temp_dir, installation) = setup();
+        let path = temp_dir.path().join("test.jar");
+        File::create(&path).unwrap().write_all(b"test").unwrap();
+        assert_eq!("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08", installation.hash_file(&path, ChecksumAlgorithm::Sha256).unwrap());
     }
 
     #[test]
@@ -397,12 +956,64 @@ mod tests {
         File::create(&subdir.join("test.txt")).unwrap().write_all(b"sub").unwrap();
 
         assert_eq!(11, installation.size(path.as_path()));
-        let files = installation.lock(&path);
+        let files = installation.lock(&path).unwrap();
         assert_eq!(3, files.len());
-        assert_eq!("a1911db12774eca1371894923dd3870595d52185797e43972e808a901555faa1", installation.hash_dir(path.as_path(), &files));
+        assert_eq!("a1911db12774eca1371894923dd3870595d52185797e43972e808a901555faa1", installation.hash_dir(path.as_path(), &files, ChecksumAlgorithm::Blake3).unwrap());
+        installation.unlock(files);
+    }
+
+    #[test]
+    fn test_hash_removed_file() {
+        let (temp_dir, installation) = setup();
+        let path = temp_dir.path().join("lib");
+        fs::create_dir(&path).unwrap();
+        File::create(path.join("test.jar")).unwrap().write_all(b"test").unwrap();
+        File::create(path.join("main.jar")).unwrap().write_all(b"main").unwrap();
+
+        // removed by a third party after it has been listed and locked
+        let files = installation.lock(&path).unwrap();
+        fs::remove_file(path.join("main.jar")).unwrap();
+        assert!(installation.hash_dir(&path, &files, ChecksumAlgorithm::Blake3).is_err());
+        assert!(installation.hash_file(&path.join("main.jar"), ChecksumAlgorithm::Blake3).is_err());
         installation.unlock(files);
     }
 
+    #[test]
+    fn test_size_many_files() {
+        let (temp_dir, installation) = setup();
+        for i in 0..50 {
+            let subdir = temp_dir.path().join(format!("dir{}", i % 5));
+            fs::create_dir_all(&subdir).unwrap();
+            File::create(subdir.join(format!("file{}.txt", i))).unwrap().write_all(&vec![b'x'; i]).unwrap();
+        }
+
+        let serial_size: u64 = walkdir::WalkDir::new(temp_dir.path())
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.metadata().ok())
+            .filter(|metadata| metadata.is_file())
+            .fold(0, |acc, m| acc + m.len());
+        assert_eq!((0..50).sum::<u64>(), serial_size);
+        assert_eq!(serial_size, installation.size(temp_dir.path()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_size_hash_symlink() {
+        let (temp_dir, installation) = setup();
+        let path = temp_dir.path().join("jvm");
+        fs::create_dir_all(path.join("lib")).unwrap();
+        File::create(path.join("lib/libjvm.so")).unwrap().write_all(b"test").unwrap();
+        std::os::unix::fs::symlink("lib/libjvm.so", path.join("libjvm.so")).unwrap();
+
+        // link is measured like it is hashed: by its target "lib/libjvm.so"
+        assert_eq!(4 + 13, installation.size(&path));
+        assert_eq!(13, installation.size(&path.join("libjvm.so")));
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"lib/libjvm.so");
+        assert_eq!(hasher.finalize().to_hex().as_str(), installation.hash_file(&path.join("libjvm.so"), ChecksumAlgorithm::Blake3).unwrap());
+    }
+
     #[test]
     fn test_empty() {
         let (temp_dir, installation) = setup();
@@ -548,12 +1159,8 @@ mod tests {
         installation.move_to_trash("lib/component.jar").unwrap();
 
         let components: Vec<ApplicationComponent> = vec!(ApplicationComponent {
-            path: String::from("lib/component.jar"),
-            url: String::from("http://host/file"),
-            checksum: String::from(""),
             download_size: Some(50),
-            size: 123,
-            cache_path: None,
+            ..ApplicationComponent::for_test("lib/component.jar", 123, "")
         });
         installation.restore_backup(&components);
 
@@ -562,13 +1169,326 @@ mod tests {
         assert_eq!("OK", contents);
     }
 
-    fn setup() -> (TempDir, InstallationManager) {
-        let temporary_dir = tempfile::tempdir().unwrap();
-        let path = temporary_dir.path();
+    #[test]
+    fn test_rollback() {
+        let (_, installation) = setup();
+
+        let existing = installation.path("lib/existing.jar");
+        fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        File::create(&existing).unwrap().write_all("OK".as_bytes()).unwrap();
+        installation.move_to_trash("lib/existing.jar").unwrap();
+        File::create(&existing).unwrap().write_all("partial".as_bytes()).unwrap();
+
+        let new = installation.path("lib/new.jar");
+        File::create(&new).unwrap().write_all("partial".as_bytes()).unwrap();
+
+        let components: Vec<ApplicationComponent> = ["lib/existing.jar", "lib/new.jar"].iter()
+            .map(|path| ApplicationComponent::for_test(path, 2, "")).collect();
+        installation.rollback(&components).unwrap();
+
+        let mut contents = String::new();
+        File::open(&existing).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!("OK", contents);
+        assert_eq!(false, new.exists());
+    }
+
+    #[test]
+    fn test_purge_backup() {
+        let (_temp_dir, installation) = setup();
+        // nothing to purge
+        installation.purge_backup().unwrap();
+
+        let existing = installation.path("lib/existing.jar");
+        fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        File::create(&existing).unwrap().write_all("old".as_bytes()).unwrap();
+        installation.move_to_trash("lib/existing.jar").unwrap();
+        File::create(&existing).unwrap().write_all("new".as_bytes()).unwrap();
+
+        installation.purge_backup().unwrap();
+        assert_eq!(false, installation.backup_path("lib/existing.jar").exists());
+        assert_eq!(false, installation.path(super::BACKUP_DIR).exists());
+        assert!(existing.exists());
+    }
 
-        let installation_manager = InstallationManager {
-            root_dir: PathBuf::from(path)
+    #[test]
+    fn test_interrupted_update() {
+        let (_temp_dir, installation) = setup();
+        let component = ApplicationComponent::for_test("lib/component.jar", 3, "");
+        let components = vec![component];
+        let path = installation.path("lib/component.jar");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "old").unwrap();
+
+        // interrupted after the backup, before the new version is written
+        installation.path_for_write("lib/component.jar").unwrap();
+        installation.restore_backup(&components);
+        assert_eq!("old", fs::read_to_string(&path).unwrap());
+
+        // interrupted while writing the new version
+        fs::write(installation.path_for_write("lib/component.jar").unwrap(), "ne").unwrap();
+        installation.restore_backup(&components);
+        assert_eq!("old", fs::read_to_string(&path).unwrap());
+
+        // interrupted while restoring, after the new version was removed
+        installation.path_for_write("lib/component.jar").unwrap();
+        installation.restore_backup(&components);
+        assert_eq!("old", fs::read_to_string(&path).unwrap());
+
+        // interrupted while removing the backup of a verified update
+        fs::write(installation.path_for_write("lib/component.jar").unwrap(), "new").unwrap();
+        fs::rename(installation.path(super::BACKUP_DIR), installation.path(super::PURGED_BACKUP_DIR)).unwrap();
+        installation.restore_backup(&components);
+        assert_eq!("new", fs::read_to_string(&path).unwrap());
+        installation.purge_backup().unwrap();
+        assert!(!installation.path(super::PURGED_BACKUP_DIR).exists());
+        assert_eq!("new", fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_check_mode() {
+        let (temp_dir, installation) = setup();
+        File::create(temp_dir.path().join("java")).unwrap().write_all(b"test").unwrap();
+        let component = ApplicationComponent {
+            mode: Some(0o755),
+            ..ApplicationComponent::for_test("java", 4, "4878ca0425c739fa427f7eda20fe845f6b2e46ba5fe2a14df5b1e32f50603215")
         };
+        fs::set_permissions(temp_dir.path().join("java"), fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(installation.check_component(component.clone()), NotOk(_, CheckFailure::Mode { expected: 0o755 })));
+
+        installation.apply_mode(&component).unwrap();
+        assert!(matches!(installation.check_component(component), OkLocked(_)));
+    }
+
+    #[test]
+    fn test_check_validators() {
+        let (temp_dir, installation) = setup();
+        // same size, but modified content
+        File::create(temp_dir.path().join("app.jar")).unwrap().write_all(b"TEST").unwrap();
+        let component = ApplicationComponent::for_test("app.jar", 4, "4878ca0425c739fa427f7eda20fe845f6b2e46ba5fe2a14df5b1e32f50603215");
+        assert!(matches!(installation.check_component(component.clone()), NotOk(_, CheckFailure::Checksum { .. })));
+        assert!(matches!(installation.check_component_with(component.clone(), &QUICK_VALIDATION), OkLocked(_)));
+
+        // order defines which failure is reported
+        fs::remove_file(temp_dir.path().join("app.jar")).unwrap();
+        assert!(matches!(installation.check_component_with(component.clone(), &QUICK_VALIDATION), NotOk(_, CheckFailure::Missing)));
+        assert!(matches!(installation.check_component_with(component.clone(), &[Validator::Size, Validator::Exists]), NotOk(_, CheckFailure::Size { expected: 4, actual: 0 })));
+    }
+
+    #[test]
+    fn test_installation_checksum() {
+        let (temp_dir, installation) = setup();
+        let descriptor = ApplicationDescriptor::parse(TEST_DESCRIPTOR, "app", None).unwrap();
+        fs::create_dir_all(temp_dir.path().join("lib")).unwrap();
+        fs::write(temp_dir.path().join("lib/app.jar"), "test").unwrap();
+        fs::write(temp_dir.path().join("splash"), "test").unwrap();
+        assert_eq!(descriptor.checksum(), installation.installation_checksum(&descriptor).unwrap());
+
+        fs::write(temp_dir.path().join("lib/app.jar"), "TEST").unwrap();
+        assert_ne!(descriptor.checksum(), installation.installation_checksum(&descriptor).unwrap());
+
+        fs::remove_file(temp_dir.path().join("lib/app.jar")).unwrap();
+        assert!(installation.installation_checksum(&descriptor).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_post_install() {
+        let (temp_dir, installation) = setup();
+        let content = format!("post_install = [\"bin/setup.sh\", \"done\"]\n{}", TEST_DESCRIPTOR.replace("lib/app.jar", "bin/setup.sh"));
+        let descriptor = ApplicationDescriptor::parse(&content, "app", None).unwrap();
+        fs::create_dir_all(temp_dir.path().join("bin")).unwrap();
+        fs::write(temp_dir.path().join("bin/setup.sh"), "#!/bin/sh\necho $1 >> setup.log\n").unwrap();
+        fs::set_permissions(temp_dir.path().join("bin/setup.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        installation.run_post_install(&descriptor).unwrap();
+        // only once per version
+        installation.run_post_install(&descriptor).unwrap();
+        assert_eq!("done\n", fs::read_to_string(temp_dir.path().join("setup.log")).unwrap());
+
+        let failing_descriptor = ApplicationDescriptor::parse(&content.replace("1.0", "2.0").replace("4878ca", "0000ca"), "app", None).unwrap();
+        fs::write(temp_dir.path().join("bin/setup.sh"), "#!/bin/sh\nexit 3\n").unwrap();
+        assert!(installation.run_post_install(&failing_descriptor).is_err());
+    }
+
+    #[test]
+    fn test_recently_verified() {
+        let (temp_dir, mut installation) = setup();
+        let clock = Arc::new(ManualClock::new());
+        installation.clock = clock.clone();
+        let descriptor = ApplicationDescriptor::parse(TEST_DESCRIPTOR, "app", None).unwrap();
+        let max_age = Duration::from_secs(3600);
+        assert!(!installation.is_recently_verified(&descriptor, max_age));
+
+        installation.store_verified(&descriptor).unwrap();
+        assert!(installation.is_recently_verified(&descriptor, max_age));
+        assert!(!installation.is_recently_verified(&descriptor, Duration::ZERO));
+        clock.advance(max_age);
+        assert!(!installation.is_recently_verified(&descriptor, max_age));
+        installation.store_verified(&descriptor).unwrap();
+
+        // another version
+        let updated_descriptor = ApplicationDescriptor::parse(&TEST_DESCRIPTOR.replace("1.0", "2.0").replace("4878ca", "0000ca"), "app", None).unwrap();
+        assert!(!installation.is_recently_verified(&updated_descriptor, max_age));
+
+        let verified_file = temp_dir.path().join(super::VERIFIED_FILE_NAME);
+        fs::write(&verified_file, format!("{} 100", descriptor.checksum())).unwrap();
+        assert!(!installation.is_recently_verified(&descriptor, max_age));
+        fs::write(&verified_file, format!("{} {}", descriptor.checksum(), u64::MAX)).unwrap();
+        assert!(!installation.is_recently_verified(&descriptor, max_age));
+    }
+
+    #[test]
+    fn test_check_components_with_progress() {
+        let (temp_dir, installation) = setup();
+        File::create(temp_dir.path().join("a")).unwrap().write_all(b"test").unwrap();
+        let component = |path: &str, size: u64| ApplicationComponent::for_test(path, size, "");
+        let components = vec![component("a", 4), component("b", 6)];
+        let progress = Mutex::new(Vec::new());
+        let results = installation.check_components_with_progress(&components, &QUICK_VALIDATION, |checked, total| {
+            progress.lock().unwrap().push((checked, total));
+            return Ok(());
+        });
+        assert_eq!(2, results.unwrap().len());
+        let cancelled = installation.check_components_with_progress(&components, &QUICK_VALIDATION, |_, _| bail!(ErrorKind::Cancelled));
+        assert!(matches!(cancelled, Err(Error(ErrorKind::Cancelled, _))));
+
+        let mut progress = progress.into_inner().unwrap();
+        progress.sort();
+        assert_eq!(2, progress.len());
+        assert!(progress.iter().all(|(_, total)| *total == 10));
+        assert_eq!(10, progress[1].0);
+    }
+
+    #[test]
+    fn test_installed_version() {
+        let (_temp_dir, installation) = setup();
+        assert_eq!(None, installation.installed_version());
+
+        installation.store_descriptor(&String::from("name = \"app\"\nversion = \"1.2\"\n")).unwrap();
+        assert_eq!(Some((String::from("app"), String::from("1.2"))), installation.installed_version());
+
+        // the backup of a running update is not restored
+        let backup_path = installation.backup_path(DESCRIPTOR_FILE_NAME);
+        fs::create_dir_all(backup_path.parent().unwrap()).unwrap();
+        fs::write(&backup_path, "name = \"app\"\nversion = \"1.1\"\n").unwrap();
+        assert_eq!(Some((String::from("app"), String::from("1.2"))), installation.installed_version());
+        assert!(backup_path.exists());
+    }
+
+    #[test]
+    fn test_locate() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cache_dir = temp_dir.path().join("cache");
+        let installation = InstallationManager::locate_in("app", Some(cache_dir.clone()), None).unwrap();
+        assert_eq!(cache_dir.join("app"), installation.get_installation_root());
+        assert_eq!(None, installation.installed_version());
+        assert!(!cache_dir.exists());
+
+        let shared_dir = temp_dir.path().join("shared");
+        let installation = InstallationManager::locate_in("app", Some(cache_dir.clone()), Some(shared_dir.clone().into_os_string())).unwrap();
+        assert_eq!(shared_dir.join("app"), installation.get_installation_root());
+        assert_eq!(cache_dir.join("app"), installation.get_user_dir());
+        assert!(!shared_dir.exists());
+
+        assert!(InstallationManager::locate_in("app", None, None).is_err());
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        assert_eq!(DEFAULT_LOCK_TIMEOUT, InstallationManager::lock_timeout(None));
+        assert_eq!(Duration::from_secs(5), InstallationManager::lock_timeout(Some(String::from(" 5"))));
+        assert_eq!(DEFAULT_LOCK_TIMEOUT, InstallationManager::lock_timeout(Some(String::from("forever"))));
+    }
+
+    #[test]
+    fn test_wait_lock() {
+        let (_temp_dir, mut installation) = setup();
+        let clock = Arc::new(ManualClock::new());
+        installation.clock = clock.clone();
+        installation.lock_timeout = Duration::from_secs(5);
+
+        // held by another launch, the timeout elapses on the manual clock without waiting
+        let lock = installation.lock_for_update().unwrap();
+        let start = clock.now();
+        let error = installation.lock_for_update().err().unwrap();
+        assert!(error.to_string().contains("within 5 seconds"));
+        assert!(clock.elapsed(start) >= Duration::from_secs(5));
+        assert!(clock.elapsed(start) < Duration::from_secs(5) + super::LOCK_RETRY_INTERVAL);
+
+        drop(lock);
+        let start = clock.now();
+        assert!(installation.lock_for_update().is_ok());
+        assert_eq!(Duration::ZERO, clock.elapsed(start));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_read_only_installation() {
+        let (temp_dir, installation) = setup();
+        // installed by another user
+        drop(installation.lock_for_update().unwrap());
+        fs::set_permissions(temp_dir.path().join(".launcher.lock"), fs::Permissions::from_mode(0o444)).unwrap();
+        fs::set_permissions(temp_dir.path(), fs::Permissions::from_mode(0o555)).unwrap();
+
+        let lock = installation.lock_for_update();
+        // permissions are not checked for root
+        let root = unsafe { libc::geteuid() } == 0;
+        let writable = installation.is_writable();
+        fs::set_permissions(temp_dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert!(lock.is_ok());
+        assert_eq!(root, writable);
+        assert!(!temp_dir.path().join(super::WRITE_TEST_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_lock_vanished_file() {
+        let (temp_dir, installation) = setup();
+        File::create(temp_dir.path().join("file")).unwrap().write_all(b"test").unwrap();
+        assert_eq!(1, installation.lock(&temp_dir.path().join("file")).unwrap().len());
+
+        // removed by a concurrent update between check and lock
+        assert!(installation.lock(&temp_dir.path().join("vanished")).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_descriptor() {
+        let (_temp_dir, installation) = setup();
+        installation.store_descriptor(&String::from("name = \"app\"")).unwrap();
+        assert_eq!("name = \"app\"", installation.get_descriptor().unwrap());
+
+        // truncated between two runs, only discarded if not just read
+        fs::write(installation.path(DESCRIPTOR_FILE_NAME), "name = ").unwrap();
+        assert_eq!(None, installation.read_descriptor());
+        assert!(installation.path(DESCRIPTOR_FILE_NAME).exists());
+        assert_eq!(None, installation.get_descriptor());
+        assert_eq!(false, installation.path(DESCRIPTOR_FILE_NAME).exists());
+
+        // descriptor stored by an older version without checksum
+        fs::write(installation.path(DESCRIPTOR_FILE_NAME), "name = \"app\"").unwrap();
+        assert_eq!("name = \"app\"", installation.get_descriptor().unwrap());
+    }
+
+    #[test]
+    fn test_descriptor_cache_info() {
+        let (_temp_dir, installation) = setup();
+        let cache_info = HttpCacheInfo {
+            etag: Some(String::from("\"abc\"")),
+            last_modified: Some(String::from("Wed, 21 Oct 2015 07:28:00 GMT")),
+        };
+        installation.store_descriptor_cache_info(&cache_info).unwrap();
+
+        // cache info without stored descriptor is useless
+        assert_eq!(HttpCacheInfo::default(), installation.get_descriptor_cache_info());
+
+        installation.store_descriptor(&String::from("name = \"app\"")).unwrap();
+        assert_eq!(cache_info, installation.get_descriptor_cache_info());
+    }
+
+    fn setup() -> (TempDir, InstallationManager) {
+        let temporary_dir = tempfile::tempdir().unwrap();
+        let installation_manager = InstallationManager::with_root(temporary_dir.path());
         return (temporary_dir, installation_manager);
     }
 }
\ No newline at end of file
@@ -1,102 +1,325 @@
-use crate::descriptor::ApplicationComponent;
+use crate::descriptor::{ApplicationComponent, ApplicationDescriptor};
 use crate::download_manager::DownloadManager;
 use crate::errors::*;
+use crate::installation_manager::{CheckFailure, Validator, FULL_VALIDATION, QUICK_VALIDATION};
 use crate::installation_manager::CheckResult::{NotOk, OkLocked};
 use crate::installation_manager::InstallationManager;
-use crate::{descriptor, jvm_starter, UserInterface};
+use crate::{jvm_starter, UserInterface};
 use cluFlock::FlockLock;
 use log::*;
 use simplelog::*;
+use std::env;
 use std::fs::File;
-use std::time::Instant;
+use std::io;
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 
+const LOG_LEVEL_ENV: &str = "NATIVESTART_LOG";
+/// set once the logger is initialized, it is kept if the launch is retried
+static LOGGER_INITIALIZED: AtomicBool = AtomicBool::new(false);
+const LOG_CONSOLE_ENV: &str = "NATIVESTART_LOG_CONSOLE";
+const VALIDATION_ENV: &str = "NATIVESTART_VALIDATION";
+const DESCRIPTOR_URL_ENV: &str = "NATIVESTART_DESCRIPTOR_URL";
+/// signed release builds ignore the descriptor URL override, so it cannot be used to redirect them
+const DESCRIPTOR_URL_OVERRIDE: bool = cfg!(any(debug_assertions, not(feature = "check-signature")));
+
 pub struct JavaLauncher {
 
 }
 
+#[derive(PartialEq, Clone, Copy)]
+pub enum LaunchMode {
+    /// download and validate the application, then start it
+    Start,
+    /// download and validate the application without starting it
+    InstallOnly,
+    /// validate the existing installation without downloading anything
+    VerifyOnly,
+    /// print what would be downloaded, deleted and started without modifying the installation
+    DryRun,
+}
+
 impl JavaLauncher {
+    /// a fully validated installation is only validated quickly when starting it within this time
+    const VERIFICATION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
     pub fn run(application_name: &'static str, application_descriptor_url: &str, public_key: Option<[u8; 32]>,
-               ui: UserInterface) -> Result<()> {
+               ui: UserInterface, mode: LaunchMode) -> Result<()> {
         let start = Instant::now();
         let installation_manager = InstallationManager::new(application_name)?;
 
-        let log_file = installation_manager.get_log_file()?;
-        let mut builder = ConfigBuilder::new();
-        let config = if builder.set_time_offset_to_local().is_ok() {
-            builder.set_time_offset_to_local().unwrap().build()
-        } else {
-            builder.build()
-        };
-        CombinedLogger::init(
-            vec![
-                WriteLogger::new(LevelFilter::Debug, config, log_file)
-            ]
-        ).chain_err(|| ErrorKind::StorageError(format!("Could not create logger")))?;
+        if !LOGGER_INITIALIZED.load(Ordering::SeqCst) {
+            let mut builder = ConfigBuilder::new();
+            let config = if builder.set_time_offset_to_local().is_ok() {
+                builder.set_time_offset_to_local().unwrap().build()
+            } else {
+                builder.build()
+            };
+            let log_level = JavaLauncher::log_level(env::var(LOG_LEVEL_ENV).ok());
+            let level = log_level.unwrap_or(JavaLauncher::DEFAULT_LOG_LEVEL);
+            let mut loggers: Vec<Box<dyn SharedLogger>> = Vec::new();
+            // a dry run does not modify the installation, including the log of the last launch
+            if mode != LaunchMode::DryRun {
+                loggers.push(WriteLogger::new(level, config.clone(), installation_manager.get_log_file()?));
+            }
+            if JavaLauncher::has_console() {
+                loggers.push(WriteLogger::new(level, config, io::stderr()));
+            }
+            CombinedLogger::init(loggers)
+                .chain_err(|| ErrorKind::StorageError(format!("Could not create logger")))?;
+            LOGGER_INITIALIZED.store(true, Ordering::SeqCst);
+            if log_level.is_err() {
+                warn!("Invalid log level in {}, using {}", LOG_LEVEL_ENV, JavaLauncher::DEFAULT_LOG_LEVEL);
+            }
+        }
+        if let Some(channel) = crate::take_invalid_channel() {
+            warn!("Invalid channel {}, using {}", channel, crate::DEFAULT_CHANNEL);
+        }
 
-        let download_manager = DownloadManager::new();
+        let application_descriptor_url = &JavaLauncher::descriptor_url(application_descriptor_url, env::var(DESCRIPTOR_URL_ENV).ok(),
+                                                                        DESCRIPTOR_URL_OVERRIDE);
+        let download_manager = DownloadManager::new(application_descriptor_url, DownloadManager::headers_from_env());
 
+        // a shared installation of another user is only validated and started, it is updated by its owner
+        let read_only = mode != LaunchMode::DryRun && !installation_manager.is_writable();
+        if read_only {
+            warn!("Installation {:?} is not writable, using it without updates", installation_manager.get_installation_root());
+        }
+        // held until the installation is complete, launches in parallel wait and then find a valid installation
+        let update_lock = match mode {
+            LaunchMode::DryRun => None,
+            // there is nothing to wait for if the owner never created the lock
+            _ if read_only => installation_manager.lock_for_update().ok(),
+            _ => Some(installation_manager.lock_for_update()?)
+        };
+        // neither a dry run nor a read-only installation restores backups or removes a corrupted descriptor
+        let stored_descriptor = || if mode == LaunchMode::DryRun || read_only {
+            installation_manager.read_descriptor()
+        } else {
+            installation_manager.get_descriptor()
+        };
+        debug!("Using installation in {:?}", installation_manager.get_installation_root());
         debug!("Using application descriptor from {}", application_descriptor_url);
-        let descriptor_content;
-        if !installation_manager.is_descriptor_locked()? {
-            descriptor_content = download_manager.download_and_get(&application_descriptor_url)
-                .and_then(|content| {
-                    installation_manager.store_descriptor(&content).unwrap();
-                    Some(content)
-                })
-                .or_else(|| installation_manager.get_descriptor())
-                .chain_err(|| ErrorKind::DownloadError("Could not download application descriptor. Internet connection is required for first usage.".to_string()))?;
+        let descriptor;
+        let mut offline = false;
+        // set if the descriptor could not be downloaded because of an error which may not occur again
+        let mut offline_transient = false;
+        if mode == LaunchMode::VerifyOnly || read_only {
+            offline = true;
+            let stored_content = stored_descriptor()
+                .chain_err(|| ErrorKind::ValidationError("Application is not installed".to_string()))?;
+            descriptor = ApplicationDescriptor::parse(&stored_content, application_name, public_key)?;
+        } else if mode == LaunchMode::DryRun || !installation_manager.is_descriptor_locked()? {
+            let cache_info = installation_manager.get_descriptor_cache_info();
+            let downloaded = download_manager.download_and_get_if_modified(application_descriptor_url, &cache_info)
+                .and_then(|result| match result {
+                    Some(downloaded) => Ok(downloaded),
+                    None => {
+                        info!("Application descriptor not modified, using stored one");
+                        let stored_content = stored_descriptor()
+                            .chain_err(|| ErrorKind::StorageError("Stored application descriptor is missing".to_string()))?;
+                        Ok((stored_content, cache_info))
+                    }
+                });
+            descriptor = match downloaded {
+                Ok((content, cache_info)) => match ApplicationDescriptor::parse(&content, application_name, public_key) {
+                    Ok(desc) => {
+                        // only replace the stored descriptor once the new one is trusted
+                        if mode != LaunchMode::DryRun {
+                            installation_manager.store_descriptor(&content)?;
+                            installation_manager.store_descriptor_cache_info(&cache_info)?;
+                        }
+                        desc
+                    }
+                    // a dry run validates the new descriptor
+                    Err(e) if mode == LaunchMode::DryRun => return Err(e),
+                    Err(e) => {
+                        warn!("Downloaded application descriptor is invalid, using stored one: {}", e);
+                        let stored_content = installation_manager.get_descriptor().ok_or(e)?;
+                        ApplicationDescriptor::parse(&stored_content, application_name, public_key)?
+                    }
+                },
+                Err(e) if mode == LaunchMode::DryRun => return Err(e),
+                Err(e) => {
+                    if DownloadManager::is_unreachable(&e) {
+                        info!("Server not reachable: {}", e);
+                    } else {
+                        warn!("{}", e);
+                    }
+                    offline_transient = e.kind().is_transient();
+                    let stored_content = match installation_manager.get_descriptor() {
+                        Some(stored_content) => stored_content,
+                        None => {
+                            // keep the kind of the error, it decides whether the launch can be retried
+                            let message = format!("Could not download application descriptor ({}). Internet connection is required for first usage.", e);
+                            bail!(match e.kind() {
+                                ErrorKind::NetworkError(_) => ErrorKind::NetworkError(message),
+                                ErrorKind::HttpStatusError(status, _) => ErrorKind::HttpStatusError(*status, message),
+                                _ => ErrorKind::DownloadError(message)
+                            });
+                        }
+                    };
+                    offline = true;
+                    ApplicationDescriptor::parse(&stored_content, application_name, public_key)?
+                }
+            };
         } else {
-            descriptor_content = installation_manager.get_descriptor().unwrap();
+            let stored_content = stored_descriptor()
+                .chain_err(|| ErrorKind::StorageError("Stored application descriptor is missing".to_string()))?;
+            descriptor = ApplicationDescriptor::parse(&stored_content, application_name, public_key)?;
+        }
+        if mode == LaunchMode::DryRun {
+            return JavaLauncher::print_plan(&installation_manager, &descriptor);
         }
         let mut locked_files: Vec<Vec<FlockLock<File>>> = Vec::new();
         locked_files.push(vec![installation_manager.lock_descriptor()?]);
 
-        let descriptor = descriptor::ApplicationDescriptor::parse(&descriptor_content, public_key)?;
-
-        // download splash screen if required
-        match installation_manager.check_component(descriptor.splash.clone()) {
-            NotOk(splash) => {
-                download_manager.download_and_store(&vec![splash], &installation_manager, &ui)?;
-                match installation_manager.check_component(descriptor.splash.clone()) {
-                    NotOk(_) => {
-                        bail!("Could not download splash screen. Please try again. If the problem persist, please contact the application author");
+        // download splash screen if required, the built-in splash screen is used if there is none or it stays invalid
+        let splash_valid = match &descriptor.splash {
+            None => false,
+            Some(splash) => match installation_manager.check_component(splash.clone()) {
+                NotOk(splash, reason) if mode == LaunchMode::VerifyOnly => {
+                    JavaLauncher::log_check_failure(&splash, &reason);
+                    bail!(JavaLauncher::incomplete_offline_installation(mode, offline_transient));
+                }
+                NotOk(splash, reason) if offline => {
+                    // the splash screen must not prevent the offline launch of an otherwise valid installation
+                    JavaLauncher::log_check_failure(&splash, &reason);
+                    warn!("Splash screen cannot be downloaded offline, using built-in splash screen");
+                    false
+                }
+                NotOk(splash, reason) => {
+                    JavaLauncher::log_check_failure(&splash, &reason);
+                    download_manager.download_and_store(&vec![splash.clone()], &installation_manager, &ui)?;
+                    match installation_manager.check_component(splash) {
+                        NotOk(splash, reason) => {
+                            error!("Downloaded {} is invalid, using built-in splash screen: {}", splash.path, reason);
+                            false
+                        }
+                        OkLocked(files) => {
+                            locked_files.push(files);
+                            true
+                        }
                     }
-                    OkLocked(files) => locked_files.push(files)
+                }
+                OkLocked(files) => {
+                    locked_files.push(files);
+                    true
                 }
             }
-            OkLocked(files) => locked_files.push(files)
+        };
+        let splash_dir = descriptor.splash.as_ref()
+            .filter(|_| splash_valid)
+            .map(|splash| installation_manager.get_installation_root().to_path_buf().join(splash.path.clone()));
+        ui.show_splash(descriptor.version.clone(), splash_dir, installation_manager.get_splash_position_file());
+        if offline && !read_only && mode == LaunchMode::Start {
+            // informational only, the installed version is started
+            ui.set_offline();
         }
-        ui.show_splash(descriptor.version.clone(),
-                       installation_manager.get_installation_root().to_path_buf().join(descriptor.splash.path.clone()));
 
         info!("Preparing {} version {}", descriptor.name, descriptor.version);
-        installation_manager.restore_backup(&descriptor.components);
+        if mode != LaunchMode::VerifyOnly && !read_only {
+            installation_manager.restore_backup(&descriptor.components);
+        }
 
         let mut files_to_download: Vec<ApplicationComponent> = Vec::new();
-        for check_result in installation_manager.check_components(&descriptor.components) {
+        let recently_verified = installation_manager.is_recently_verified(&descriptor, JavaLauncher::VERIFICATION_INTERVAL);
+        if recently_verified {
+            debug!("Installation of version {} was fully validated recently", descriptor.version);
+        }
+        let validators = JavaLauncher::validators(mode, env::var(VALIDATION_ENV).ok(), recently_verified);
+        let verification_progress = |checked_bytes: u64, total_bytes: u64| -> Result<()> {
+            ui.set_verification_progress(checked_bytes, total_bytes);
+            if ui.is_cancelled() {
+                bail!(ErrorKind::Cancelled);
+            }
+            return Ok(());
+        };
+        if validators == &FULL_VALIDATION {
+            // hashing all files of a large installation takes a while
+            ui.set_verifying();
+        }
+        // nothing was changed yet, locks of already checked components are released when dropped
+        let check_results = installation_manager.check_components_with_progress(&descriptor.components, validators, &verification_progress)?;
+        for check_result in check_results {
             match check_result {
-                NotOk(component) => files_to_download.push(component),
+                NotOk(component, reason) => {
+                    JavaLauncher::log_check_failure(&component, &reason);
+                    files_to_download.push(component)
+                },
                 OkLocked(files) => locked_files.push(files)
             }
         }
-        download_manager.download_and_store(&files_to_download, &installation_manager, &ui)?;
-        for result in installation_manager.check_components(&files_to_download) {
+        if offline {
+            if !files_to_download.is_empty() && read_only && mode != LaunchMode::VerifyOnly {
+                bail!(ErrorKind::StorageError(format!("Installation {:?} is incomplete and cannot be updated by this user. Please start the application as the user who installed it.",
+                                                      installation_manager.get_installation_root())));
+            }
+            if !files_to_download.is_empty() {
+                bail!(JavaLauncher::incomplete_offline_installation(mode, offline_transient));
+            }
+            if mode != LaunchMode::VerifyOnly && !read_only {
+                info!("Application descriptor could not be downloaded, launching installed version offline");
+            }
+        } else if let Err(e) = download_manager.download_and_store(&files_to_download, &installation_manager, &ui) {
+            if ui.is_cancelled() {
+                info!("Download cancelled by user");
+                installation_manager.rollback(&files_to_download)?;
+                bail!(ErrorKind::Cancelled);
+            }
+            return Err(e);
+        }
+        if !files_to_download.is_empty() {
+            ui.set_verifying();
+        }
+        let check_results = match installation_manager.check_components_with_progress(&files_to_download, &FULL_VALIDATION, &verification_progress) {
+            Ok(check_results) => check_results,
+            Err(e) => {
+                if ui.is_cancelled() {
+                    info!("Verification cancelled by user");
+                    installation_manager.rollback(&files_to_download)?;
+                }
+                return Err(e);
+            }
+        };
+        for result in check_results {
             match result {
-                NotOk(_) => {
+                NotOk(component, reason) => {
+                    error!("Downloaded {} is invalid: {}", component.path, reason);
                     bail!("Error during installation verification. Please try again. If the problem persist, please contact the application author");
                 }
                 OkLocked(files) => locked_files.push(files)
             }
         }
-        installation_manager.create_unmanaged(&descriptor)?;
-        installation_manager.delete_unused_files(&descriptor)?;
+        if mode != LaunchMode::VerifyOnly && !read_only {
+            // the new version is verified, the previous one is no longer required for a rollback
+            if let Err(e) = installation_manager.purge_backup() {
+                warn!("{}", e);
+            }
+            installation_manager.create_unmanaged(&descriptor)?;
+            installation_manager.delete_unused_files(&descriptor)?;
+            installation_manager.run_post_install(&descriptor)?;
+        }
+        if validators == &FULL_VALIDATION && !read_only {
+            if let Err(e) = installation_manager.store_verified(&descriptor) {
+                warn!("{}", e);
+            }
+        }
+        drop(update_lock);
+        ui.files_ready();
 
         let elapsed = start.elapsed();
         info!("Check finished in {} ms", elapsed.as_millis());
 
-        info!("Starting {} version {}", descriptor.name, descriptor.version);
-        jvm_starter::JvmStarter::start_jvm(&descriptor.jvm_params, &installation_manager.get_installation_root(), &ui)?;
+        if mode == LaunchMode::Start {
+            info!("Starting {} version {}", descriptor.name, descriptor.version);
+            jvm_starter::JvmStarter::start_jvm(&descriptor.jvm_params, &installation_manager.get_installation_root(), &ui)?;
+        } else {
+            info!("{} version {} is installed and valid", descriptor.name, descriptor.version);
+        }
 
         info!("Unlocking files");
         for f in locked_files {
@@ -105,4 +328,127 @@ impl JavaLauncher {
 
         return Ok(());
     }
+
+    /// Prints the components to download, the unused files to delete and the JVM start
+    fn print_plan(installation_manager: &InstallationManager, descriptor: &ApplicationDescriptor) -> Result<()> {
+        let mut components: Vec<ApplicationComponent> = descriptor.splash.iter().cloned().collect();
+        components.extend(descriptor.components.iter().cloned());
+
+        println!("{} version {}", descriptor.name, descriptor.version);
+        println!("Download:");
+        for check_result in installation_manager.check_components(&components) {
+            // locks of valid components are released when dropped
+            if let NotOk(component, reason) = check_result {
+                println!("  {} from {} ({})", component.path, component.url, reason);
+            }
+        }
+        println!("Delete:");
+        for path in installation_manager.get_unused_files(descriptor)? {
+            println!("  {}", path.display());
+        }
+        println!("Start:");
+        println!("  {}", jvm_starter::JvmStarter::command_line(&descriptor.jvm_params, &installation_manager.get_installation_root()));
+        return Ok(());
+    }
+
+    /// Log to the console if stderr is a terminal or if requested by `NATIVESTART_LOG_CONSOLE=1`
+    fn has_console() -> bool {
+        let requested = env::var(LOG_CONSOLE_ENV).map_or(false, |value| value == "1" || value.eq_ignore_ascii_case("true"));
+        // the Windows executable only has a console if it was started from one
+        #[cfg(target_os = "windows")]
+        if unsafe { windows::Win32::System::Console::GetConsoleWindow() }.0.is_null() {
+            return false;
+        }
+        return requested || io::stderr().is_terminal();
+    }
+
+    const DEFAULT_LOG_LEVEL: LevelFilter = if cfg!(debug_assertions) { LevelFilter::Debug } else { LevelFilter::Info };
+
+    /// Parses the configured log level (e.g. `info`). Fails for invalid values.
+    fn log_level(value: Option<String>) -> std::result::Result<LevelFilter, ()> {
+        return match value {
+            Some(value) => LevelFilter::from_str(value.trim()).map_err(|_| ()),
+            None => Ok(JavaLauncher::DEFAULT_LOG_LEVEL)
+        };
+    }
+
+    /// Returns the descriptor URL from `NATIVESTART_DESCRIPTOR_URL` if set and allowed, e.g. to test a staging descriptor
+    fn descriptor_url(compiled: &str, value: Option<String>, override_allowed: bool) -> String {
+        return match value.as_deref().map(str::trim) {
+            Some(url) if !url.is_empty() && override_allowed => {
+                warn!("Using application descriptor {} from {} instead of {}", url, DESCRIPTOR_URL_ENV, compiled);
+                url.to_string()
+            },
+            Some(url) if !url.is_empty() => {
+                warn!("Ignoring {} in a signed release build", DESCRIPTOR_URL_ENV);
+                compiled.to_string()
+            },
+            _ => compiled.to_string(),
+        };
+    }
+
+    /// When starting, `quick` or a recent full validation of the same installation skips the checksum of installed components,
+    /// downloaded components are always fully validated
+    fn validators(mode: LaunchMode, value: Option<String>, recently_verified: bool) -> &'static [Validator] {
+        let quick_requested = value.is_some_and(|value| value.trim().eq_ignore_ascii_case("quick"));
+        if mode == LaunchMode::Start && (quick_requested || recently_verified) {
+            return &QUICK_VALIDATION;
+        }
+        return &FULL_VALIDATION;
+    }
+
+    fn log_check_failure(component: &ApplicationComponent, reason: &CheckFailure) {
+        match reason {
+            CheckFailure::Missing => info!("{} needs to be downloaded: {}", component.path, reason),
+            // unexpected for an installed component, could be a modification by a third party
+            _ => warn!("{} needs to be downloaded again: {}", component.path, reason),
+        }
+    }
+
+    fn incomplete_offline_installation(mode: LaunchMode, transient: bool) -> ErrorKind {
+        if mode == LaunchMode::VerifyOnly {
+            return ErrorKind::ValidationError("Installation is incomplete or has been modified".to_string());
+        }
+        let message = "Could not download application descriptor and the installation is incomplete. Please check your internet connection.".to_string();
+        return if transient { ErrorKind::NetworkError(message) } else { ErrorKind::DownloadError(message) };
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use log::LevelFilter;
+    use crate::installation_manager::{FULL_VALIDATION, QUICK_VALIDATION};
+    use crate::java_launcher::{JavaLauncher, LaunchMode};
+
+    #[test]
+    fn test_log_level() {
+        assert_eq!(Ok(JavaLauncher::DEFAULT_LOG_LEVEL), JavaLauncher::log_level(None));
+        assert_eq!(Ok(LevelFilter::Info), JavaLauncher::log_level(Some(String::from("info"))));
+        assert_eq!(Ok(LevelFilter::Warn), JavaLauncher::log_level(Some(String::from("WARN"))));
+        assert_eq!(Ok(LevelFilter::Off), JavaLauncher::log_level(Some(String::from("off"))));
+        assert_eq!(Err(()), JavaLauncher::log_level(Some(String::from("verbose"))));
+    }
+
+    #[test]
+    fn test_validators() {
+        assert_eq!(&FULL_VALIDATION, JavaLauncher::validators(LaunchMode::Start, None, false));
+        assert_eq!(&QUICK_VALIDATION, JavaLauncher::validators(LaunchMode::Start, Some(String::from("quick")), false));
+        assert_eq!(&FULL_VALIDATION, JavaLauncher::validators(LaunchMode::Start, Some(String::from("full")), false));
+        assert_eq!(&QUICK_VALIDATION, JavaLauncher::validators(LaunchMode::Start, None, true));
+        // verification is never weakened
+        assert_eq!(&FULL_VALIDATION, JavaLauncher::validators(LaunchMode::VerifyOnly, Some(String::from("quick")), false));
+        assert_eq!(&FULL_VALIDATION, JavaLauncher::validators(LaunchMode::VerifyOnly, None, true));
+        assert_eq!(&FULL_VALIDATION, JavaLauncher::validators(LaunchMode::InstallOnly, None, true));
+    }
+
+    #[test]
+    fn test_descriptor_url() {
+        let compiled = "https://example.com/app.toml";
+        assert_eq!(compiled, JavaLauncher::descriptor_url(compiled, None, true));
+        assert_eq!(compiled, JavaLauncher::descriptor_url(compiled, Some(String::from(" ")), true));
+        assert_eq!("https://staging.example.com/app.toml",
+                   JavaLauncher::descriptor_url(compiled, Some(String::from("https://staging.example.com/app.toml")), true));
+        assert_eq!(compiled, JavaLauncher::descriptor_url(compiled, Some(String::from("https://staging.example.com/app.toml")), false));
+    }
 }
@@ -1,63 +1,145 @@
 use log::*;
 use std::env;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::ffi::{c_void, CStr};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::ptr::null_mut;
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+use std::sync::Arc;
 use std::thread;
-use std::time::Instant;
-use crate::descriptor::JvmParameters;
+use std::time::{Duration, Instant};
+use crate::descriptor::{JvmParameters, SplashClosePolicy};
 use crate::errors::*;
 use crate::UserInterface;
 use jni_simple::*;
 
+/// arguments with this prefix are meant for the launcher and not passed to the Java application
+const LAUNCHER_ARGUMENT_PREFIX: &str = "--nativestart:";
+
+/// additional JVM options, e.g. for debugging, appended to the options of the descriptor
+const JVM_OPTIONS_ENV: &str = "NATIVESTART_JVM_OPTS";
+
+/// placeholder in the JVM options of the descriptor, e.g. for class path entries if a working directory is defined
+const INSTALLATION_DIR_PLACEHOLDER: &str = "{installation_dir}";
+
+/// set for a launcher started by a supervising launcher which restarts it after a crash
+const SUPERVISED_ENV: &str = "NATIVESTART_SUPERVISED";
+
+/// bytes read from the JVM library to determine its architecture, contains the headers of all supported formats
+const LIBRARY_HEADER_SIZE: u64 = 64 * 1024;
+
+/// user interface receiving the startup progress reported by the Java application
+static APPLICATION_UI: OnceLock<UserInterface> = OnceLock::new();
+
+/// set once the splash screen has been hidden by awaitUI(), the close policy or the application
+static SPLASH_HIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// termination signal (SIGTERM, SIGINT or SIGHUP) received by the launcher, 0 if none
+static TERMINATION_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
 pub struct JvmStarter {}
 
 impl JvmStarter {
+    const DEFAULT_AWAIT_UI_TIMEOUT_SECS: u64 = 120;
+    const RESTART_DELAY: Duration = Duration::from_secs(2);
+    const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
     pub fn start_jvm(descriptor: &JvmParameters, installation_root: &PathBuf, ui: &UserInterface) -> Result<()> {
+        // a crashing JVM takes the process down, so the application runs in a child launcher process
+        if let Some(max_restarts) = descriptor.max_restarts {
+            if env::var_os(SUPERVISED_ENV).is_none() {
+                return JvmStarter::supervise(max_restarts, ui);
+            }
+        }
         unsafe {
             let start = Instant::now();
             // set PATH to the location of the native libraries needed by the JVM
             let jvm_path = installation_root.join(&descriptor.jvm_path);
             env::set_var("PATH", &jvm_path);
+            for (name, value) in JvmStarter::environment(&descriptor.env, installation_root, |name| env::var_os(name).is_some()) {
+                debug!("Setting environment variable {}", name);
+                env::set_var(name, value);
+            }
 
-            load_jvm_from_library(jvm_path.join(&descriptor.jvm_library).to_str().unwrap())
+            // loading a library of another architecture fails with a cryptic error of the operating system
+            let jvm_library = jvm_path.join(&descriptor.jvm_library);
+            if !jvm_library.starts_with(installation_root) {
+                bail!(ErrorKind::JavaExecutionError(format!("JVM library {:?} is outside the installation", jvm_library)));
+            }
+            JvmStarter::check_library_architecture(&jvm_library)?;
+            load_jvm_from_library(jvm_library.to_str().unwrap())
                 .expect("failed to load jvm");
 
-            // change to installation root (JAR locations are specified relative to this)
-            debug!("Switching to {:?}", installation_root);
-            env::set_current_dir(&installation_root)
-                .chain_err(|| ErrorKind::JavaExecutionError(format!("Could not change to installation directory {:?}", &installation_root)))?;
+            // change to working directory, by default the installation root (JAR locations are specified relative to this)
+            let working_dir = match &descriptor.working_dir {
+                Some(working_dir) => installation_root.join(working_dir),
+                None => installation_root.clone()
+            };
+            debug!("Switching to {:?}", working_dir);
+            env::set_current_dir(&working_dir)
+                .chain_err(|| ErrorKind::JavaExecutionError(format!("Could not change to working directory {:?}", &working_dir)))?;
 
-            let (jvm, env) = JNI_CreateJavaVM_with_string_args(JNI_VERSION_1_8, &descriptor.options, false).expect("failed to create jvm");
+            let options = JvmStarter::jvm_options(&descriptor.all_options(), installation_root, env::var(JVM_OPTIONS_ENV).ok());
+            debug!("Creating JVM with options {:?}", options);
+            if let Ok(tool_options) = env::var("JAVA_TOOL_OPTIONS") {
+                // evaluated by the JVM itself
+                debug!("JAVA_TOOL_OPTIONS: {}", tool_options);
+            }
+            let (jvm, env) = JNI_CreateJavaVM_with_string_args(JNI_VERSION_1_8, &options, false).expect("failed to create jvm");
+
+            let java_version = JvmStarter::java_version(&env);
+            debug!("Java version {}", java_version.as_deref().unwrap_or("unknown"));
+            if let Some(expected_version) = &descriptor.version {
+                let matches = java_version.as_ref().map_or(false, |version| JvmStarter::version_matches(expected_version, version));
+                if !matches {
+                    bail!(ErrorKind::JavaExecutionError(format!("Java version {} is required, but the installed Java runtime has version {}. Please try again. If the problem persist, please contact the application author",
+                        expected_version, java_version.as_deref().unwrap_or("unknown"))));
+                }
+            }
 
             let main_class = env.FindClass(descriptor.main_class.as_str());
             let main_method = env.GetStaticMethodID(main_class, "main", "([Ljava/lang/String;)V");
+            JvmStarter::register_splash_methods(&env, main_class, ui);
+
+            // replaces the signal handlers of the JVM, which are missing if it is started with -Xrs
+            let ui_clone = ui.clone();
+            JvmStarter::handle_termination_signals(move |signal| {
+                info!("Received signal {}, shutting down Java application", signal);
+                ui_clone.application_terminated();
+                JvmStarter::exit_jvm(128 + signal);
+            });
 
             let string_class = env.FindClass("java/lang/String");
-            let args: Vec<String> = env::args().collect();
-            let main_method_string_parameter_array = env.NewObjectArray((args.len() - 1) as i32, string_class, null_mut());
-            for i in 1..args.len() {
-                let argument = env.NewStringUTF(args[i].as_str());
-                env.SetObjectArrayElement(main_method_string_parameter_array, (i - 1) as i32, argument);
+            let args = JvmStarter::application_arguments(env::args());
+            let main_method_string_parameter_array = env.NewObjectArray(args.len() as i32, string_class, null_mut());
+            for (i, arg) in args.iter().enumerate() {
+                let argument = env.NewStringUTF(arg.as_str());
+                env.SetObjectArrayElement(main_method_string_parameter_array, i as i32, argument);
             }
 
-            let ui_clone = ui.clone();
-            let main_class_name = descriptor.main_class.clone();
-            thread::spawn(move || {
-                let jvm = JNI_GetCreatedJavaVMs_first().unwrap().unwrap();
-                jvm.AttachCurrentThreadAsDaemon_str(JNI_VERSION_1_8, "await UI", null_mut())
-                    .expect("Could not attach thread");
-                let env = jvm.GetEnv::<JNIEnv>(JNI_VERSION_1_8).unwrap();
-                let main_class = env.FindClass(main_class_name.as_str());
-                let await_ui_method = env.GetStaticMethodID(main_class, "awaitUI", "()V");
-                if !await_ui_method.is_null() {
-                    debug!("awaitUI() found in Java application. Calling it to determine when to hide splash screen");
-                    env.CallStaticVoidMethod0(main_class, await_ui_method);
-                } else {
-                    debug!("awaitUI() not found in Java application. Hide splash screen immediately");
+            match descriptor.splash_close.unwrap_or_default() {
+                SplashClosePolicy::OnUiVisible if descriptor.await_ui.unwrap_or(true) => {
+                    JvmStarter::await_ui(&descriptor.main_class, descriptor.await_ui_timeout, ui);
                 }
-                let _ = jvm.DetachCurrentThread();
-                ui_clone.application_visible();
-            });
+                SplashClosePolicy::OnUiVisible => {
+                    debug!("awaitUI() disabled by the descriptor. Hide splash screen immediately");
+                    JvmStarter::hide_splash(ui);
+                }
+                SplashClosePolicy::Timeout(millis) => {
+                    debug!("Hiding splash screen after {} ms", millis);
+                    let ui_clone = ui.clone();
+                    thread::spawn(move || {
+                        thread::sleep(Duration::from_millis(millis));
+                        JvmStarter::hide_splash(&ui_clone);
+                    });
+                }
+                SplashClosePolicy::Manual => debug!("Hiding splash screen once the application calls hideSplash()"),
+            }
 
             let elapsed = start.elapsed();
             info!("Starting JVM took {} ms", elapsed.as_millis());
@@ -75,4 +157,441 @@ impl JvmStarter {
         ui.application_terminated();
         return Ok(());
     }
+
+    /// Starts the launcher again as child process and restarts it up to max_restarts times if it terminates abnormally
+    /// (non-zero exit code or signal). The child launcher shows its own splash screen.
+    fn supervise(max_restarts: u32, ui: &UserInterface) -> Result<()> {
+        let executable = env::current_exe()
+            .chain_err(|| ErrorKind::JavaExecutionError(format!("Could not determine launcher executable")))?;
+        // the child launcher shuts down the Java application itself
+        let child_id = Arc::new(AtomicU32::new(0));
+        let signal_child_id = child_id.clone();
+        JvmStarter::handle_termination_signals(move |signal| {
+            info!("Received signal {}, forwarding it to the supervised launcher", signal);
+            JvmStarter::forward_signal(signal_child_id.load(Ordering::SeqCst), signal);
+        });
+        let mut restarts = 0;
+        loop {
+            debug!("Starting supervised launcher {:?}", executable);
+            let mut child = Command::new(&executable)
+                .args(env::args_os().skip(1))
+                .env(SUPERVISED_ENV, "1")
+                .spawn()
+                .chain_err(|| ErrorKind::JavaExecutionError(format!("Could not start launcher {:?}", &executable)))?;
+            child_id.store(child.id(), Ordering::SeqCst);
+            if restarts == 0 {
+                ui.application_visible();
+            }
+            let status = child.wait()
+                .chain_err(|| ErrorKind::JavaExecutionError(format!("Could not wait for launcher {:?}", &executable)))?;
+            if status.success() || TERMINATION_SIGNAL.load(Ordering::SeqCst) != 0 {
+                // no restart if the launcher is asked to terminate
+                break;
+            }
+            if restarts >= max_restarts {
+                bail!(ErrorKind::JavaExecutionError(format!("Application terminated abnormally ({}) after {} restarts", status, restarts)));
+            }
+            restarts += 1;
+            warn!("Application terminated abnormally ({}), restarting it ({} of {})", status, restarts, max_restarts);
+            thread::sleep(JvmStarter::RESTART_DELAY);
+        }
+
+        ui.application_terminated();
+        return Ok(());
+    }
+
+    /// Catches SIGTERM, SIGINT and SIGHUP and calls the given function with the signal from a separate thread
+    #[cfg(unix)]
+    fn handle_termination_signals<F: FnOnce(i32) + Send + 'static>(on_signal: F) {
+        extern "C" fn on_termination_signal(signal: libc::c_int) {
+            // only async-signal-safe operations are allowed here
+            TERMINATION_SIGNAL.store(signal, Ordering::SeqCst);
+        }
+        unsafe {
+            for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+                libc::signal(signal, on_termination_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+            }
+        }
+        thread::spawn(move || {
+            loop {
+                let signal = TERMINATION_SIGNAL.load(Ordering::SeqCst);
+                if signal != 0 {
+                    on_signal(signal);
+                    return;
+                }
+                thread::sleep(JvmStarter::SIGNAL_POLL_INTERVAL);
+            }
+        });
+    }
+
+    #[cfg(not(unix))]
+    fn handle_termination_signals<F: FnOnce(i32) + Send + 'static>(_on_signal: F) {
+        // the JVM handles console control events itself on Windows
+    }
+
+    #[cfg(unix)]
+    fn forward_signal(pid: u32, signal: i32) {
+        if pid != 0 {
+            unsafe {
+                libc::kill(pid as libc::pid_t, signal);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn forward_signal(_pid: u32, _signal: i32) {
+    }
+
+    /// Calls `System.exit()` in the running JVM, which runs the shutdown hooks of the application.
+    /// `DestroyJavaVM` is not suitable, as it waits for the main method to return.
+    fn exit_jvm(status: i32) {
+        unsafe {
+            let jvm = match JNI_GetCreatedJavaVMs_first() {
+                Ok(Some(jvm)) => jvm,
+                _ => return
+            };
+            let env = match jvm.AttachCurrentThreadAsDaemon_str(JNI_VERSION_1_8, "shutdown", null_mut()) {
+                Ok(env) => env,
+                Err(_) => {
+                    warn!("Could not attach to JVM to shut down the application");
+                    return;
+                }
+            };
+            let system_class = env.FindClass("java/lang/System");
+            let exit_method = env.GetStaticMethodID(system_class, "exit", "(I)V");
+            env.CallStaticVoidMethod1(system_class, exit_method, status as jint);
+        }
+    }
+
+    /// Describes the JVM start like a command line: JVM library, options, main class and application arguments
+    pub fn command_line(descriptor: &JvmParameters, installation_root: &PathBuf) -> String {
+        let jvm_library = installation_root.join(&descriptor.jvm_path).join(&descriptor.jvm_library);
+        let mut command_line: Vec<String> = JvmStarter::environment(&descriptor.env, installation_root, |name| env::var_os(name).is_some())
+            .into_iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        command_line.push(jvm_library.to_string_lossy().to_string());
+        command_line.extend(JvmStarter::jvm_options(&descriptor.all_options(), installation_root, env::var(JVM_OPTIONS_ENV).ok()));
+        command_line.push(descriptor.main_class.clone());
+        command_line.extend(JvmStarter::application_arguments(env::args()));
+        return command_line.join(" ");
+    }
+
+    /// Calls awaitUI() of the main class in a separate thread if it exists and hides the splash screen once it returned
+    /// or the timeout elapsed
+    unsafe fn await_ui(main_class: &str, await_ui_timeout: Option<u64>, ui: &UserInterface) {
+        let (ui_ready_tx, ui_ready_rx) = mpsc::channel::<()>();
+        let main_class_name = main_class.to_string();
+        thread::spawn(move || {
+            let jvm = JNI_GetCreatedJavaVMs_first().unwrap().unwrap();
+            jvm.AttachCurrentThreadAsDaemon_str(JNI_VERSION_1_8, "await UI", null_mut())
+                .expect("Could not attach thread");
+            let env = jvm.GetEnv::<JNIEnv>(JNI_VERSION_1_8).unwrap();
+            let main_class = env.FindClass(main_class_name.as_str());
+            let await_ui_method = env.GetStaticMethodID(main_class, "awaitUI", "()V");
+            if !await_ui_method.is_null() {
+                debug!("awaitUI() found in Java application. Calling it to determine when to hide splash screen");
+                env.CallStaticVoidMethod0(main_class, await_ui_method);
+            } else {
+                debug!("awaitUI() not found in Java application. Hide splash screen immediately");
+            }
+            let _ = jvm.DetachCurrentThread();
+            let _ = ui_ready_tx.send(());
+        });
+
+        // do not keep the splash screen forever if awaitUI() hangs
+        let ui_clone = ui.clone();
+        let timeout = await_ui_timeout.unwrap_or(JvmStarter::DEFAULT_AWAIT_UI_TIMEOUT_SECS);
+        thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = ui_ready_rx.recv_timeout(Duration::from_secs(timeout)) {
+                warn!("awaitUI() did not return within {} s. Hiding splash screen anyway", timeout);
+            }
+            JvmStarter::hide_splash(&ui_clone);
+        });
+    }
+
+    /// Fails with a precise error if the JVM library was built for another architecture than the launcher
+    fn check_library_architecture(library: &Path) -> Result<()> {
+        let mut header = Vec::new();
+        File::open(library).and_then(|file| file.take(LIBRARY_HEADER_SIZE).read_to_end(&mut header))
+            .chain_err(|| ErrorKind::JavaExecutionError(format!("Could not read JVM library {:?}", library)))?;
+        let architectures = match JvmStarter::library_architectures(&header) {
+            Some(architectures) => architectures,
+            None => {
+                // the loader reports the problem if it cannot load the library
+                debug!("Unknown architecture of JVM library {:?}", library);
+                return Ok(());
+            }
+        };
+        debug!("JVM library {:?} has architecture {:?}", library, architectures);
+        return match JvmStarter::architecture_mismatch(&architectures, env::consts::ARCH) {
+            Some(mismatch) => Err(ErrorKind::JavaExecutionError(format!("{}. Please contact the application author", mismatch)).into()),
+            None => Ok(())
+        };
+    }
+
+    /// Describes why a library for the given architectures cannot be loaded by the launcher, `None` if it can
+    fn architecture_mismatch(library_architectures: &[&str], launcher_architecture: &str) -> Option<String> {
+        if library_architectures.contains(&launcher_architecture) {
+            return None;
+        }
+        let bits = |architecture: &str| if architecture == "x86" || architecture == "arm" { 32 } else { 64 };
+        let launcher_bits = bits(launcher_architecture);
+        if library_architectures.iter().all(|architecture| bits(architecture) != launcher_bits) {
+            return Some(format!("{}-bit JVM cannot be loaded by {}-bit launcher", bits(library_architectures[0]), launcher_bits));
+        }
+        return Some(format!("JVM for {} cannot be loaded by {} launcher", library_architectures.join(", "), launcher_architecture));
+    }
+
+    /// Architectures (named like `std::env::consts::ARCH`) of a native library read from its ELF, PE or Mach-O header.
+    /// Returns `None` for unknown formats and architectures.
+    fn library_architectures(header: &[u8]) -> Option<Vec<&'static str>> {
+        let read_u16 = |offset: usize, little_endian: bool| -> Option<u16> {
+            let bytes: [u8; 2] = header.get(offset..offset + 2)?.try_into().ok()?;
+            return Some(if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) });
+        };
+        let read_u32 = |offset: usize, little_endian: bool| -> Option<u32> {
+            let bytes: [u8; 4] = header.get(offset..offset + 4)?.try_into().ok()?;
+            return Some(if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) });
+        };
+        let mach_o_architecture = |cpu_type: u32| match cpu_type {
+            0x7 => Some("x86"),
+            0x0100_0007 => Some("x86_64"),
+            0xC => Some("arm"),
+            0x0100_000C => Some("aarch64"),
+            _ => None
+        };
+
+        let architectures = if header.starts_with(b"\x7fELF") {
+            let little_endian = *header.get(5)? == 1;
+            let architecture = match read_u16(18, little_endian)? {
+                3 => "x86",
+                62 => "x86_64",
+                40 => "arm",
+                183 => "aarch64",
+                _ => return None
+            };
+            vec![architecture]
+        } else if header.starts_with(b"MZ") {
+            let pe_offset = read_u32(0x3C, true)? as usize;
+            if header.get(pe_offset..pe_offset + 4)? != b"PE\0\0" {
+                return None;
+            }
+            let architecture = match read_u16(pe_offset + 4, true)? {
+                0x14C => "x86",
+                0x8664 => "x86_64",
+                0x1C0 | 0x1C4 => "arm",
+                0xAA64 => "aarch64",
+                _ => return None
+            };
+            vec![architecture]
+        } else if let Some(0xFEED_FACE | 0xFEED_FACF) = read_u32(0, true) {
+            vec![mach_o_architecture(read_u32(4, true)?)?]
+        } else if let Some(magic @ (0xCAFE_BABE | 0xCAFE_BABF)) = read_u32(0, false) {
+            // universal binary containing libraries for several architectures
+            let entry_size = if magic == 0xCAFE_BABF { 32 } else { 20 };
+            let count = read_u32(4, false)?.min(16) as usize;
+            (0..count).filter_map(|index| read_u32(8 + index * entry_size, false).and_then(mach_o_architecture)).collect()
+        } else {
+            return None;
+        };
+        return if architectures.is_empty() { None } else { Some(architectures) };
+    }
+
+    /// Hides the splash screen, only the first call has an effect
+    fn hide_splash(ui: &UserInterface) {
+        if !SPLASH_HIDDEN.swap(true, Ordering::SeqCst) {
+            ui.application_visible();
+        }
+    }
+
+    /// The `java.version` system property of the created JVM
+    unsafe fn java_version(env: &JNIEnv) -> Option<String> {
+        let system_class = env.FindClass("java/lang/System");
+        let get_property = env.GetStaticMethodID(system_class, "getProperty", "(Ljava/lang/String;)Ljava/lang/String;");
+        if system_class.is_null() || get_property.is_null() {
+            env.ExceptionClear();
+            return None;
+        }
+        let version = env.CallStaticObjectMethod1(system_class, get_property, env.NewStringUTF("java.version"));
+        if version.is_null() {
+            env.ExceptionClear();
+            return None;
+        }
+        return env.GetStringUTFChars_as_string(version);
+    }
+
+    /// The expected version matches the actual version or is a prefix of it, e.g. `17` matches `17.0.1` and `17-ea`, but not `170`
+    fn version_matches(expected: &str, actual: &str) -> bool {
+        let expected = expected.trim();
+        return match actual.strip_prefix(expected) {
+            Some(rest) => !rest.starts_with(|c: char| c.is_ascii_digit()),
+            None => false
+        };
+    }
+
+    /// Options of the descriptor followed by the whitespace separated additional options, so they can override them
+    fn jvm_options(descriptor_options: &Vec<String>, installation_root: &Path, additional_options: Option<String>) -> Vec<String> {
+        let installation_dir = installation_root.to_string_lossy();
+        let mut options: Vec<String> = descriptor_options.iter()
+            .map(|option| option.replace(INSTALLATION_DIR_PLACEHOLDER, &installation_dir))
+            .collect();
+        options.extend(additional_options.unwrap_or_default().split_whitespace().map(String::from));
+        return options;
+    }
+
+    /// Environment variables of the descriptor which are not set yet, with the installation directory inserted
+    fn environment<F: Fn(&str) -> bool>(descriptor_env: &Option<BTreeMap<String, String>>, installation_root: &Path, is_set: F) -> Vec<(String, String)> {
+        let installation_dir = installation_root.to_string_lossy();
+        return descriptor_env.iter().flatten()
+            .filter(|(name, _)| !is_set(name))
+            .map(|(name, value)| (name.clone(), value.replace(INSTALLATION_DIR_PLACEHOLDER, &installation_dir)))
+            .collect();
+    }
+
+    /// Arguments for the Java application: without the executable path and the launcher arguments
+    fn application_arguments<I: Iterator<Item = String>>(args: I) -> Vec<String> {
+        return args.skip(1)
+            .filter(|arg| !arg.starts_with(LAUNCHER_ARGUMENT_PREFIX))
+            .collect();
+    }
+
+    /// Implements `static native void setSplashProgress(double progress, String status)`,
+    /// `static native void setSplashStatus(String status)` and `static native void hideSplash()` if declared by the main class.
+    unsafe fn register_splash_methods(env: &JNIEnv, main_class: jclass, ui: &UserInterface) {
+        let _ = APPLICATION_UI.set(ui.clone());
+        let methods = [JNINativeMethod {
+            name: c"setSplashProgress".as_ptr(),
+            signature: c"(DLjava/lang/String;)V".as_ptr(),
+            fnPtr: set_splash_progress as *const c_void,
+        }, JNINativeMethod {
+            name: c"setSplashStatus".as_ptr(),
+            signature: c"(Ljava/lang/String;)V".as_ptr(),
+            fnPtr: set_splash_status as *const c_void,
+        }, JNINativeMethod {
+            name: c"hideSplash".as_ptr(),
+            signature: c"()V".as_ptr(),
+            fnPtr: hide_splash as *const c_void,
+        }];
+        // registered one by one, as the application may declare only some of the methods
+        for method in methods {
+            let name = CStr::from_ptr(method.name).to_string_lossy();
+            if env.RegisterNatives(main_class, &[method]) == JNI_OK {
+                debug!("{}() found in Java application. Showing it on the splash screen", name);
+            } else {
+                // the method is not declared -> clear the NoSuchMethodError
+                env.ExceptionClear();
+                debug!("{}() not found in Java application", name);
+            }
+        }
+    }
+}
+
+extern "system" fn set_splash_progress(env: JNIEnv, _class: jclass, progress: jdouble, status: jstring) {
+    let status = if status.is_null() { None } else { unsafe { env.GetStringUTFChars_as_string(status) } };
+    if let Some(ui) = APPLICATION_UI.get() {
+        ui.set_application_progress(progress, status);
+    }
+}
+
+extern "system" fn set_splash_status(env: JNIEnv, _class: jclass, status: jstring) {
+    if status.is_null() {
+        return;
+    }
+    if let (Some(status), Some(ui)) = (unsafe { env.GetStringUTFChars_as_string(status) }, APPLICATION_UI.get()) {
+        ui.set_application_status(status);
+    }
+}
+
+extern "system" fn hide_splash(_env: JNIEnv, _class: jclass) {
+    if let Some(ui) = APPLICATION_UI.get() {
+        JvmStarter::hide_splash(ui);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use crate::jvm_starter::JvmStarter;
+
+    #[test]
+    fn test_environment() {
+        let descriptor_env = Some(BTreeMap::from([
+            (String::from("APP_CONFIG"), String::from("{installation_dir}/config")),
+            (String::from("APP_MODE"), String::from("kiosk")),
+        ]));
+        let root = Path::new("/opt/app");
+        assert_eq!(vec![(String::from("APP_CONFIG"), String::from("/opt/app/config")), (String::from("APP_MODE"), String::from("kiosk"))],
+                   JvmStarter::environment(&descriptor_env, root, |_| false));
+        // the inherited environment takes precedence
+        assert_eq!(vec![(String::from("APP_CONFIG"), String::from("/opt/app/config"))],
+                   JvmStarter::environment(&descriptor_env, root, |name| name == "APP_MODE"));
+        assert!(JvmStarter::environment(&None, root, |_| false).is_empty());
+    }
+
+    #[test]
+    fn test_library_architectures() {
+        let mut elf = vec![0u8; 64];
+        elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        elf[18..20].copy_from_slice(&62u16.to_le_bytes());
+        assert_eq!(Some(vec!["x86_64"]), JvmStarter::library_architectures(&elf));
+        elf[18..20].copy_from_slice(&183u16.to_le_bytes());
+        assert_eq!(Some(vec!["aarch64"]), JvmStarter::library_architectures(&elf));
+
+        let mut pe = vec![0u8; 256];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3C..0x40].copy_from_slice(&128u32.to_le_bytes());
+        pe[128..132].copy_from_slice(b"PE\0\0");
+        pe[132..134].copy_from_slice(&0x14Cu16.to_le_bytes());
+        assert_eq!(Some(vec!["x86"]), JvmStarter::library_architectures(&pe));
+
+        let mut universal = vec![0u8; 64];
+        universal[..4].copy_from_slice(&0xCAFE_BABEu32.to_be_bytes());
+        universal[4..8].copy_from_slice(&2u32.to_be_bytes());
+        universal[8..12].copy_from_slice(&0x0100_0007u32.to_be_bytes());
+        universal[28..32].copy_from_slice(&0x0100_000Cu32.to_be_bytes());
+        assert_eq!(Some(vec!["x86_64", "aarch64"]), JvmStarter::library_architectures(&universal));
+
+        assert_eq!(None, JvmStarter::library_architectures(b"no library"));
+        assert_eq!(None, JvmStarter::library_architectures(&pe[..100]));
+    }
+
+    #[test]
+    fn test_architecture_mismatch() {
+        assert_eq!(None, JvmStarter::architecture_mismatch(&["x86_64"], "x86_64"));
+        assert_eq!(None, JvmStarter::architecture_mismatch(&["x86_64", "aarch64"], "aarch64"));
+        assert_eq!(Some(String::from("32-bit JVM cannot be loaded by 64-bit launcher")), JvmStarter::architecture_mismatch(&["x86"], "x86_64"));
+        assert_eq!(Some(String::from("64-bit JVM cannot be loaded by 32-bit launcher")), JvmStarter::architecture_mismatch(&["x86_64"], "x86"));
+        assert_eq!(Some(String::from("JVM for aarch64 cannot be loaded by x86_64 launcher")), JvmStarter::architecture_mismatch(&["aarch64"], "x86_64"));
+    }
+
+    #[test]
+    fn test_application_arguments() {
+        let args = ["/opt/app/launcher", "--nativestart:verify", "file.txt", "--verbose", "--nativestart:"]
+            .iter().map(|arg| String::from(*arg));
+        assert_eq!(vec!["file.txt", "--verbose"], JvmStarter::application_arguments(args));
+    }
+
+    #[test]
+    fn test_version_matches() {
+        assert!(JvmStarter::version_matches("17", "17"));
+        assert!(JvmStarter::version_matches("17", "17.0.1"));
+        assert!(JvmStarter::version_matches("17", "17-ea"));
+        assert!(JvmStarter::version_matches("17.0.1", "17.0.1"));
+        assert!(JvmStarter::version_matches("1.8", "1.8.0_392"));
+        assert!(!JvmStarter::version_matches("17", "170"));
+        assert!(!JvmStarter::version_matches("17.0.1", "17.0.10"));
+        assert!(!JvmStarter::version_matches("17", "21.0.2"));
+    }
+
+    #[test]
+    fn test_jvm_options() {
+        let descriptor_options = vec![String::from("-Xmx1g")];
+        let root = Path::new("/opt/app");
+        assert_eq!(vec!["-Xmx1g"], JvmStarter::jvm_options(&descriptor_options, root, None));
+        assert_eq!(vec!["-Xmx1g", "-Xmx2g", "-Xlog:gc"], JvmStarter::jvm_options(&descriptor_options, root, Some(String::from(" -Xmx2g  -Xlog:gc "))));
+
+        let class_path = vec![String::from("-Djava.class.path={installation_dir}/lib/app.jar")];
+        assert_eq!(vec!["-Djava.class.path=/opt/app/lib/app.jar"], JvmStarter::jvm_options(&class_path, root, None));
+    }
 }
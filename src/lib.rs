@@ -1,22 +1,27 @@
 #[macro_use]
 extern crate error_chain;
 
+use std::io::{self, IsTerminal, Write};
 use std::path::PathBuf;
 use std::process;
-use std::sync::mpsc;
-use std::sync::mpsc::Receiver;
+use std::sync::{mpsc, Mutex};
+use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
 
 use error_chain::ChainedError;
 use log::*;
 use msgbox::IconType;
 
-use java_launcher::JavaLauncher;
+use descriptor::ApplicationDescriptor;
+use installation_manager::InstallationManager;
+use java_launcher::{JavaLauncher, LaunchMode};
 use ui::UserInterface;
 
-use crate::ui::Message;
+use crate::errors::{Error, ErrorKind};
+pub use crate::ui::{DownloadProgress, Message, MAX_DOWNLOAD_PROGRESS};
 
 mod errors;
+mod clock;
 mod java_launcher;
 mod ui;
 mod descriptor;
@@ -35,28 +40,229 @@ pub fn start(application_name: &'static str, application_descriptor_url: String,
     start_internal(application_name, application_descriptor_url, Some(application_public_key));
 }
 
-fn start_internal(application_name: &'static str, application_descriptor_url: String, application_public_key: Option<[u8; 32]>) {
+/// Runs the launcher in a separate thread without any user interface. The returned channel receives all progress and
+/// error events, so other frontends (CLI, GUI, service) can be built on top of it. The channel gets disconnected once
+/// the launcher is done.
+#[cfg(not(feature = "check-signature"))]
+pub fn run_with_events(application_name: &'static str, application_descriptor_url: String) -> Receiver<Message> {
+    return spawn_launcher(application_name, application_descriptor_url, None);
+}
+
+/// Runs the launcher in a separate thread without any user interface. The returned channel receives all progress and
+/// error events, so other frontends (CLI, GUI, service) can be built on top of it. The channel gets disconnected once
+/// the launcher is done.
+#[cfg(feature = "check-signature")]
+pub fn run_with_events(application_name: &'static str, application_descriptor_url: String, application_public_key: [u8; 32]) -> Receiver<Message> {
+    return spawn_launcher(application_name, application_descriptor_url, Some(application_public_key));
+}
+
+/// Download and validate the application without starting it. Returns the process exit code.
+#[cfg(not(feature = "check-signature"))]
+pub fn install(application_name: &'static str, application_descriptor_url: String) -> i32 {
+    return run_without_ui(application_name, application_descriptor_url, None, LaunchMode::InstallOnly);
+}
+
+/// Download and validate the application without starting it. Returns the process exit code.
+#[cfg(feature = "check-signature")]
+pub fn install(application_name: &'static str, application_descriptor_url: String, application_public_key: [u8; 32]) -> i32 {
+    return run_without_ui(application_name, application_descriptor_url, Some(application_public_key), LaunchMode::InstallOnly);
+}
+
+/// Validate the existing installation without downloading anything. Returns the process exit code.
+#[cfg(not(feature = "check-signature"))]
+pub fn verify(application_name: &'static str, application_descriptor_url: String) -> i32 {
+    return run_without_ui(application_name, application_descriptor_url, None, LaunchMode::VerifyOnly);
+}
+
+/// Validate the existing installation without downloading anything. Returns the process exit code.
+#[cfg(feature = "check-signature")]
+pub fn verify(application_name: &'static str, application_descriptor_url: String, application_public_key: [u8; 32]) -> i32 {
+    return run_without_ui(application_name, application_descriptor_url, Some(application_public_key), LaunchMode::VerifyOnly);
+}
+
+/// Print what would be downloaded, deleted and started without modifying the installation. Returns the process exit code.
+#[cfg(not(feature = "check-signature"))]
+pub fn dry_run(application_name: &'static str, application_descriptor_url: String) -> i32 {
+    return run_without_ui(application_name, application_descriptor_url, None, LaunchMode::DryRun);
+}
+
+/// Print what would be downloaded, deleted and started without modifying the installation. Returns the process exit code.
+#[cfg(feature = "check-signature")]
+pub fn dry_run(application_name: &'static str, application_descriptor_url: String, application_public_key: [u8; 32]) -> i32 {
+    return run_without_ui(application_name, application_descriptor_url, Some(application_public_key), LaunchMode::DryRun);
+}
+
+/// Exit code if the installation is not valid
+pub const EXIT_CODE_INVALID: i32 = 2;
+
+/// Location and version of an installation, e.g. for support tools
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstallationStatus {
+    /// directory containing the application and the JVM
+    pub root_dir: PathBuf,
+    /// per user directory containing the log file, equal to `root_dir` unless the installation is shared
+    pub user_dir: PathBuf,
+    /// name and version of the installed application, `None` if it is not installed yet
+    pub installed: Option<(String, String)>,
+}
+
+/// Returns where the application is installed and which version is present without launching, updating or validating it.
+/// Nothing is created, the directories may not exist if the application was never installed.
+/// Returns `None` if the installation directory cannot be determined.
+pub fn installation_status(application_name: &'static str) -> Option<InstallationStatus> {
+    let installation_manager = InstallationManager::locate(application_name).ok()?;
+    return Some(InstallationStatus {
+        root_dir: installation_manager.get_installation_root(),
+        user_dir: installation_manager.get_user_dir(),
+        installed: installation_manager.installed_version(),
+    });
+}
+
+/// Channel used if neither the environment variable nor the command line argument selects one
+pub const DEFAULT_CHANNEL: &str = "stable";
+const CHANNEL_ENV: &str = "NATIVESTART_CHANNEL";
+const CHANNEL_ARGUMENT: &str = "--nativestart:channel=";
+/// invalid channel requested before the logger was initialized, it is logged by the launcher
+static INVALID_CHANNEL: Mutex<Option<String>> = Mutex::new(None);
+
+/// Returns the release channel (e.g. `stable` or `beta`) to insert into the descriptor URL.
+/// The command line argument `--nativestart:channel=<name>` takes precedence over the environment variable `NATIVESTART_CHANNEL`.
+pub fn channel() -> String {
+    return match select_channel(std::env::args(), std::env::var(CHANNEL_ENV).ok()) {
+        Ok(channel) => channel,
+        Err(channel) => {
+            *INVALID_CHANNEL.lock().unwrap() = Some(channel);
+            DEFAULT_CHANNEL.to_string()
+        }
+    };
+}
+
+/// Returns the invalid channel requested by the last call of `channel()`, so it can be logged once the logger is initialized
+pub(crate) fn take_invalid_channel() -> Option<String> {
+    return INVALID_CHANNEL.lock().unwrap().take();
+}
+
+/// Returns the selected channel or the rejected name if it is not valid
+fn select_channel(args: impl Iterator<Item = String>, env: Option<String>) -> std::result::Result<String, String> {
+    let argument = args.filter_map(|arg| arg.strip_prefix(CHANNEL_ARGUMENT).map(String::from)).last();
+    let channel = match argument.or(env) {
+        Some(channel) if !channel.trim().is_empty() => channel.trim().to_string(),
+        _ => return Ok(DEFAULT_CHANNEL.to_string()),
+    };
+    // the channel becomes part of the URL, so only allow simple names which are not a relative path like ..
+    if !channel.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') || channel.chars().all(|c| c == '.') {
+        return Err(channel);
+    }
+    return Ok(channel);
+}
+
+fn run_without_ui(application_name: &'static str, application_descriptor_url: String, application_public_key: Option<[u8; 32]>,
+                  mode: LaunchMode) -> i32 {
+    // nobody is showing the messages for the splash screen
+    let (tx, _rx) = mpsc::channel();
+    let ui = UserInterface::new(tx);
+    return match JavaLauncher::run(application_name, &application_descriptor_url, application_public_key, ui, mode) {
+        Ok(_) => {
+            if mode != LaunchMode::DryRun {
+                println!("Installation of {} is valid", application_name);
+            }
+            0
+        },
+        Err(Error(ErrorKind::ValidationError(message), _)) => {
+            eprintln!("Installation of {} is not valid: {}", application_name, message);
+            EXIT_CODE_INVALID
+        },
+        Err(e) => {
+            error!("{}", e.display_chain().to_string());
+            eprintln!("{}", e);
+            1
+        }
+    };
+}
+
+/// Print the combined checksum of the installed components, e.g. for the attestation by security tooling.
+/// Returns the process exit code, `0` if the checksum matches the stored descriptor.
+#[cfg(not(feature = "check-signature"))]
+pub fn checksum(application_name: &'static str) -> i32 {
+    return print_checksum(application_name, None);
+}
+
+/// Print the combined checksum of the installed components, e.g. for the attestation by security tooling.
+/// Returns the process exit code, `0` if the checksum matches the stored descriptor.
+#[cfg(feature = "check-signature")]
+pub fn checksum(application_name: &'static str, application_public_key: [u8; 32]) -> i32 {
+    return print_checksum(application_name, Some(application_public_key));
+}
+
+fn print_checksum(application_name: &'static str, application_public_key: Option<[u8; 32]>) -> i32 {
+    let checksums = InstallationManager::new(application_name).and_then(|installation_manager| {
+        let content = installation_manager.get_descriptor()
+            .ok_or_else(|| Error::from(ErrorKind::ValidationError("Application is not installed".to_string())))?;
+        let descriptor = ApplicationDescriptor::parse(&content, application_name, application_public_key)?;
+        let actual = installation_manager.installation_checksum(&descriptor)?;
+        return Ok((actual, descriptor.checksum()));
+    });
+    return match checksums {
+        Ok((actual, expected)) if actual == expected => {
+            println!("{}", actual);
+            0
+        },
+        Ok((actual, expected)) => {
+            println!("{}", actual);
+            eprintln!("Installation of {} does not match its descriptor (expected {})", application_name, expected);
+            EXIT_CODE_INVALID
+        },
+        Err(Error(ErrorKind::ValidationError(message), _)) => {
+            eprintln!("Installation of {} is not valid: {}", application_name, message);
+            EXIT_CODE_INVALID
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    };
+}
+
+fn spawn_launcher(application_name: &'static str, application_descriptor_url: String, application_public_key: Option<[u8; 32]>) -> Receiver<Message> {
     // create communication channel
     let (tx, rx) = mpsc::channel();
     let ui = UserInterface::new(tx);
 
-    // start launcher in separate thread - this thread is reserved for UI stuff (required by macOS)
     thread::spawn(move || {
-        let result = JavaLauncher::run(&application_name, &application_descriptor_url, application_public_key, ui.clone());
-        match result {
-            Ok(_) => {},
-            Err(e) => {
-                error!("{}", e.display_chain().to_string());
-                ui.terminate(format!("{:}", e));
+        loop {
+            let result = JavaLauncher::run(&application_name, &application_descriptor_url, application_public_key, ui.clone(), LaunchMode::Start);
+            match result {
+                Ok(_) => {},
+                Err(Error(ErrorKind::Cancelled, _)) => {
+                    // the channel gets disconnected, which ends the user interface
+                    info!("Cancelled by user");
+                }
+                Err(e) => {
+                    error!("{}", e.display_chain().to_string());
+                    if e.kind().is_transient() && ui.ask_retry(format!("{:}", e)) {
+                        info!("Retrying launch");
+                        continue;
+                    }
+                    ui.terminate(format!("{:}", e));
+                }
             }
+            break;
         }
     });
+    return rx;
+}
+
+fn start_internal(application_name: &'static str, application_descriptor_url: String, application_public_key: Option<[u8; 32]>) {
+    // start launcher in separate thread - this thread is reserved for UI stuff (required by macOS)
+    let rx = spawn_launcher(application_name, application_descriptor_url, application_public_key);
+    // detect the desktop theme while the descriptor is downloaded
+    ui::splash::Splash::probe_appearance();
 
     // wait until splash can be shown and provide an error message dialog functionality
-    let (version, image_dir) = await_splash(&application_name, &rx);
+    let (version, image_dir, position_file) = await_splash(&application_name, &rx);
 
     // show splash and download progress
-    let mut splash = ui::splash::Splash::new(&application_name, version, image_dir);
+    let mut splash = ui::splash::Splash::new(&application_name, version, image_dir, position_file);
     match splash.show_and_await_termination(rx) {
         Err(e) => {
             error!("{}", e.display_chain().to_string());
@@ -79,20 +285,79 @@ pub fn show_error_message(application_name: &'static str, message: String, termi
     }
 }
 
-fn await_splash(application_name: &'static str, rx: &Receiver<Message>) -> (String, PathBuf) {
+/// Lets the user decide whether to retry after a transient error. Without a display the user is asked on the console,
+/// the launcher terminates with the error message if no retry is requested.
+pub fn ask_retry(application_name: &'static str, message: String, retry: Sender<bool>) {
+    let answer = match ui::splash::Splash::show_retry_dialog(application_name, &message) {
+        Some(true) => true,
+        // the user has seen the error already
+        Some(false) => process::exit(1),
+        None => ask_retry_on_console(&message)
+    };
+    // the launcher is waiting for the answer as long as the user interface runs
+    let _ = retry.send(answer);
+}
+
+/// Asks on the console whether to retry, false if there is no interactive console
+fn ask_retry_on_console(message: &str) -> bool {
+    if !io::stdin().is_terminal() {
+        return false;
+    }
+    let mut console = ui::console_output();
+    let _ = write!(console, "{}\nRetry? [y/N] ", message);
+    let _ = console.flush();
+    let mut answer = String::new();
+    return io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y");
+}
+
+fn await_splash(application_name: &'static str, rx: &Receiver<Message>) -> (String, Option<PathBuf>, PathBuf) {
     loop {
         match rx.recv() {
             Ok(Message::Error(val)) => {
                 show_error_message(&application_name, val, true);
             },
+            Ok(Message::RetryableError(val, retry)) => {
+                ask_retry(&application_name, val, retry);
+            },
             Err(e) => {
                 error!("{}", e);
                 show_error_message(&application_name, String::from(e.to_string()), true);
             },
-            Ok(Message::SplashReady(version, image_dir)) => {
-                return (version, image_dir);
+            Ok(Message::SplashReady(version, image_dir, position_file)) => {
+                return (version, image_dir, position_file);
             },
             Ok(_) => ()
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        return args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().into_iter();
+    }
+
+    #[test]
+    fn test_installation_status_of_missing_application() {
+        let status = installation_status("nativestart-status-never-installed").unwrap();
+        assert_eq!(None, status.installed);
+        assert!(!status.root_dir.exists());
+        assert!(!status.user_dir.exists());
+    }
+
+    #[test]
+    fn test_select_channel() {
+        assert_eq!(select_channel(args(&["app"]), None), Ok("stable".to_string()));
+        assert_eq!(select_channel(args(&["app"]), Some("beta".to_string())), Ok("beta".to_string()));
+        assert_eq!(select_channel(args(&["app", "--nativestart:channel=beta"]), None), Ok("beta".to_string()));
+        assert_eq!(select_channel(args(&["app", "--nativestart:channel=nightly"]), Some("beta".to_string())), Ok("nightly".to_string()));
+        assert_eq!(select_channel(args(&["app"]), Some(" ".to_string())), Ok("stable".to_string()));
+        assert_eq!(select_channel(args(&["app", "--nativestart:channel=1.2"]), None), Ok("1.2".to_string()));
+        assert_eq!(select_channel(args(&["app", "--nativestart:channel=../other"]), None), Err("../other".to_string()));
+        assert_eq!(select_channel(args(&["app", "--nativestart:channel=beta?x=1"]), None), Err("beta?x=1".to_string()));
+        assert_eq!(select_channel(args(&["app", "--nativestart:channel=.."]), None), Err("..".to_string()));
+        assert_eq!(select_channel(args(&["app"]), Some(".".to_string())), Err(".".to_string()));
+    }
+}
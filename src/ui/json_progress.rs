@@ -0,0 +1,78 @@
+use std::sync::atomic::Ordering;
+use std::env;
+
+use crate::ui::{Message, MAX_DOWNLOAD_PROGRESS};
+
+const PROGRESS_OUTPUT_ENV: &str = "NATIVESTART_PROGRESS";
+
+/// Progress is written as JSON lines to stdout if requested by `NATIVESTART_PROGRESS=json`
+pub fn is_enabled() -> bool {
+    return env::var(PROGRESS_OUTPUT_ENV).map(|value| value.eq_ignore_ascii_case("json")).unwrap_or(false);
+}
+
+/// Converts a message to a JSON line for processes embedding the launcher
+pub fn to_json_line(message: &Message) -> Option<String> {
+    return match message {
+        Message::Error(message) => Some(format!("{{\"phase\":\"error\",\"message\":{}}}", json_string(message))),
+        Message::RetryableError(message, _) => Some(format!("{{\"phase\":\"error\",\"message\":{},\"retryable\":true}}", json_string(message))),
+        Message::SplashReady(version, _, _) => Some(format!("{{\"phase\":\"preparing\",\"version\":{}}}", json_string(version))),
+        Message::Downloading(progress) => Some(format!(
+            "{{\"phase\":\"downloading\",\"progress\":{},\"downloaded_bytes\":{},\"total_bytes\":{},\"file\":{},\"file_count\":{}}}",
+            progress.progress.load(Ordering::SeqCst).min(MAX_DOWNLOAD_PROGRESS) as f64 / MAX_DOWNLOAD_PROGRESS as f64,
+            progress.downloaded_bytes.load(Ordering::SeqCst),
+            progress.total_bytes.load(Ordering::SeqCst),
+            progress.file_index.load(Ordering::SeqCst),
+            progress.file_count.load(Ordering::SeqCst))),
+        Message::Offline => Some(String::from("{\"phase\":\"offline\"}")),
+        Message::Verifying(progress) => Some(format!("{{\"phase\":\"verifying\",\"progress\":{}}}",
+            progress.progress.load(Ordering::SeqCst).min(MAX_DOWNLOAD_PROGRESS) as f64 / MAX_DOWNLOAD_PROGRESS as f64)),
+        Message::FilesReady => Some(String::from("{\"phase\":\"starting\"}")),
+        Message::ApplicationProgress(progress, status) => Some(format!("{{\"phase\":\"application\",\"progress\":{},\"status\":{}}}",
+            progress, status.as_ref().map_or(String::from("null"), |status| json_string(status)))),
+        Message::ApplicationStatus(status) => Some(format!("{{\"phase\":\"application\",\"status\":{}}}", json_string(status))),
+        Message::ApplicationUiVisible => Some(String::from("{\"phase\":\"visible\"}")),
+        Message::ApplicationTerminated => Some(String::from("{\"phase\":\"terminated\"}")),
+    };
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c)
+        }
+    }
+    result.push('"');
+    return result;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc};
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+    use crate::ui::json_progress::to_json_line;
+    use crate::ui::{Message, VerificationProgress};
+
+    #[test]
+    fn test_to_json_line() {
+        assert_eq!("{\"phase\":\"starting\"}", to_json_line(&Message::FilesReady).unwrap());
+        let progress = Arc::new(VerificationProgress { progress: AtomicUsize::new(250), cancelled: AtomicBool::new(false) });
+        assert_eq!("{\"phase\":\"verifying\",\"progress\":0.25}", to_json_line(&Message::Verifying(progress)).unwrap());
+        assert_eq!("{\"phase\":\"error\",\"message\":\"Could not \\\"download\\\"\\nC:\\\\app\"}",
+                   to_json_line(&Message::Error(String::from("Could not \"download\"\nC:\\app"))).unwrap());
+        let (tx, _rx) = mpsc::channel();
+        assert_eq!("{\"phase\":\"error\",\"message\":\"Timeout\",\"retryable\":true}",
+                   to_json_line(&Message::RetryableError(String::from("Timeout"), tx)).unwrap());
+        assert_eq!("{\"phase\":\"application\",\"progress\":0.5,\"status\":null}",
+                   to_json_line(&Message::ApplicationProgress(0.5, None)).unwrap());
+        assert_eq!("{\"phase\":\"application\",\"status\":\"Connecting\"}",
+                   to_json_line(&Message::ApplicationStatus(String::from("Connecting"))).unwrap());
+    }
+}
@@ -1,25 +1,80 @@
+use std::io::{self, Write};
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::mpsc::Sender;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SendError, Sender};
 
 pub mod splash;
+mod json_progress;
 
 
+/// Events sent by the launcher to the user interface
 pub enum Message {
+    /// launching failed with the given message
     Error(String),
-    SplashReady(String, PathBuf),
-    Downloading(Arc<AtomicUsize>),
+    /// launching failed with an error which may not occur again (e.g. a network failure): the launch is retried
+    /// if `true` is sent back, it fails with `Error` if `false` is sent back or the sender is dropped
+    RetryableError(String, Sender<bool>),
+    /// version, splash directory and splash position file once the splash screen is available,
+    /// the splash directory is missing if the splash screen is invalid and the application starts without it
+    SplashReady(String, Option<PathBuf>, PathBuf),
+    /// the descriptor could not be downloaded, the installed version is started without checking for updates
+    Offline,
+    /// download started, the progress is updated continuously
+    Downloading(Arc<DownloadProgress>),
+    /// the installed files are verified, the progress is updated continuously
+    Verifying(Arc<VerificationProgress>),
+    /// all files are available and verified, Java is about to be started
     FilesReady,
+    /// progress and status reported by the Java application
+    ApplicationProgress(f64, Option<String>),
+    /// status reported by the Java application without progress
+    ApplicationStatus(String),
     ApplicationUiVisible,
     ApplicationTerminated,
 }
 pub const MAX_DOWNLOAD_PROGRESS: usize = 1000;
 
+/// Console output for users without splash screen, stderr if stdout is reserved for the JSON progress
+pub fn console_output() -> Box<dyn Write> {
+    if json_progress::is_enabled() {
+        return Box::new(io::stderr());
+    }
+    return Box::new(io::stdout());
+}
+
+pub struct DownloadProgress {
+    /// progress between 0 and MAX_DOWNLOAD_PROGRESS
+    pub progress: AtomicUsize,
+    pub downloaded_bytes: AtomicU64,
+    pub total_bytes: AtomicU64,
+    /// smoothed download rate, 0 if not yet known
+    pub bytes_per_second: AtomicU64,
+    /// file currently downloaded (starting with 1) of file_count files
+    pub file_index: AtomicUsize,
+    pub file_count: AtomicUsize,
+    pub file_downloaded_bytes: AtomicU64,
+    pub file_total_bytes: AtomicU64,
+    /// set while the current file is completely downloaded, but still being extracted
+    pub extracting: AtomicBool,
+    /// set by the splash if the user wants to abort the download
+    pub cancelled: AtomicBool,
+}
+
+pub struct VerificationProgress {
+    /// progress between 0 and MAX_DOWNLOAD_PROGRESS
+    pub progress: AtomicUsize,
+    /// set by the splash if the user wants to abort the verification
+    pub cancelled: AtomicBool,
+}
+
 #[derive(Clone)]
 pub struct UserInterface {
     tx: Sender<Message>,
-    download_progress: Arc<AtomicUsize>,
+    download_progress: Arc<DownloadProgress>,
+    verification_progress: Arc<VerificationProgress>,
+    /// write progress as JSON lines to stdout
+    json_progress: bool,
 }
 
 impl UserInterface {
@@ -28,40 +83,140 @@ impl UserInterface {
     pub fn new(tx: Sender<Message>) -> UserInterface {
         return UserInterface {
             tx,
-            download_progress : Arc::new(AtomicUsize::new(UserInterface::NOT_INITIALIZED)),
+            download_progress : Arc::new(DownloadProgress {
+                progress: AtomicUsize::new(UserInterface::NOT_INITIALIZED),
+                downloaded_bytes: AtomicU64::new(0),
+                total_bytes: AtomicU64::new(0),
+                bytes_per_second: AtomicU64::new(0),
+                file_index: AtomicUsize::new(0),
+                file_count: AtomicUsize::new(0),
+                file_downloaded_bytes: AtomicU64::new(0),
+                file_total_bytes: AtomicU64::new(0),
+                extracting: AtomicBool::new(false),
+                cancelled: AtomicBool::new(false),
+            }),
+            verification_progress: Arc::new(VerificationProgress {
+                progress: AtomicUsize::new(0),
+                cancelled: AtomicBool::new(false),
+            }),
+            json_progress: json_progress::is_enabled(),
         };
     }
 
     pub fn terminate(&self, message: String) {
-        self.tx.send(Message::Error(message)).unwrap();
+        self.send(Message::Error(message)).unwrap();
     }
 
-    pub fn show_splash(&self, version: String, image_dir: PathBuf) {
-        self.tx.send(Message::SplashReady(version, image_dir)).unwrap();
+    /// Asks the user whether to retry after a transient error, false if the user interface does not answer
+    pub fn ask_retry(&self, message: String) -> bool {
+        let (tx, rx) = mpsc::channel();
+        self.send(Message::RetryableError(message, tx)).unwrap();
+        return rx.recv().unwrap_or(false);
     }
 
-    pub fn set_download_progress(&self, progress: f64) {
-        let old_progress = self.download_progress.load(Ordering::SeqCst);
+    pub fn show_splash(&self, version: String, image_dir: Option<PathBuf>, position_file: PathBuf) {
+        self.send(Message::SplashReady(version, image_dir, position_file)).unwrap();
+    }
+
+    pub fn set_offline(&self) {
+        self.send(Message::Offline).unwrap();
+    }
+
+    pub fn set_download_progress(&self, downloaded_bytes: u64, total_bytes: u64) {
+        let progress = if total_bytes > 0 { downloaded_bytes as f64 / total_bytes as f64 } else { 1.0 };
+        let old_progress = self.download_progress.progress.load(Ordering::SeqCst);
         let new_progress = (progress * MAX_DOWNLOAD_PROGRESS as f64) as usize;
 
+        self.download_progress.downloaded_bytes.store(downloaded_bytes, Ordering::SeqCst);
+        self.download_progress.total_bytes.store(total_bytes, Ordering::SeqCst);
         if new_progress != old_progress {
-            self.download_progress.store(new_progress, Ordering::SeqCst);
+            self.download_progress.progress.store(new_progress, Ordering::SeqCst);
+            if self.json_progress && old_progress != UserInterface::NOT_INITIALIZED {
+                self.print_json(&Message::Downloading(self.download_progress.clone()));
+            }
         }
         if old_progress == UserInterface::NOT_INITIALIZED {
-            self.tx.send(Message::Downloading(self.download_progress.clone())).unwrap();
+            self.send(Message::Downloading(self.download_progress.clone())).unwrap();
         }
     }
 
+    pub fn set_download_file(&self, file_index: usize, file_count: usize, file_total_bytes: u64) {
+        self.download_progress.file_index.store(file_index, Ordering::SeqCst);
+        self.download_progress.file_count.store(file_count, Ordering::SeqCst);
+        self.download_progress.file_downloaded_bytes.store(0, Ordering::SeqCst);
+        self.download_progress.file_total_bytes.store(file_total_bytes, Ordering::SeqCst);
+        self.download_progress.extracting.store(false, Ordering::SeqCst);
+    }
+
+    pub fn set_extracting(&self, extracting: bool) {
+        self.download_progress.extracting.store(extracting, Ordering::SeqCst);
+    }
+
+    pub fn set_file_progress(&self, file_downloaded_bytes: u64) {
+        self.download_progress.file_downloaded_bytes.store(file_downloaded_bytes, Ordering::SeqCst);
+    }
+
+    pub fn set_download_speed(&self, bytes_per_second: u64) {
+        self.download_progress.bytes_per_second.store(bytes_per_second, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        return self.download_progress.cancelled.load(Ordering::SeqCst)
+            || self.verification_progress.cancelled.load(Ordering::SeqCst);
+    }
+
     pub fn download_done(&self) {
-        self.tx.send(Message::FilesReady).unwrap();
-        self.download_progress.store(UserInterface::NOT_INITIALIZED, Ordering::SeqCst);
+        self.download_progress.extracting.store(false, Ordering::SeqCst);
+        self.download_progress.progress.store(UserInterface::NOT_INITIALIZED, Ordering::SeqCst);
+    }
+
+    pub fn set_verifying(&self) {
+        self.verification_progress.progress.store(0, Ordering::SeqCst);
+        self.send(Message::Verifying(self.verification_progress.clone())).unwrap();
+    }
+
+    pub fn set_verification_progress(&self, checked_bytes: u64, total_bytes: u64) {
+        let progress = if total_bytes > 0 { checked_bytes as f64 / total_bytes as f64 } else { 1.0 };
+        let new_progress = (progress.min(1.0) * MAX_DOWNLOAD_PROGRESS as f64) as usize;
+        let old_progress = self.verification_progress.progress.swap(new_progress, Ordering::SeqCst);
+        if self.json_progress && new_progress != old_progress {
+            self.print_json(&Message::Verifying(self.verification_progress.clone()));
+        }
+    }
+
+    pub fn files_ready(&self) {
+        self.send(Message::FilesReady).unwrap();
+    }
+
+    pub fn set_application_progress(&self, progress: f64, status: Option<String>) {
+        // the splash may already be closed
+        let _ = self.send(Message::ApplicationProgress(progress.clamp(0.0, 1.0), status));
+    }
+
+    pub fn set_application_status(&self, status: String) {
+        // the splash may already be closed
+        let _ = self.send(Message::ApplicationStatus(status));
     }
 
     pub fn application_visible(&self) {
-        self.tx.send(Message::ApplicationUiVisible).unwrap();
+        self.send(Message::ApplicationUiVisible).unwrap();
     }
 
     pub fn application_terminated(&self) {
-        self.tx.send(Message::ApplicationTerminated).unwrap();
+        // the user interface may already be closed, e.g. if the launcher is terminated by a signal
+        let _ = self.send(Message::ApplicationTerminated);
+    }
+
+    fn send(&self, message: Message) -> Result<(), SendError<Message>> {
+        if self.json_progress {
+            self.print_json(&message);
+        }
+        return self.tx.send(message);
+    }
+
+    fn print_json(&self, message: &Message) {
+        if let Some(line) = json_progress::to_json_line(message) {
+            println!("{}", line);
+        }
     }
 }
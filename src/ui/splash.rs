@@ -1,24 +1,137 @@
-use std::time::Duration;
-use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
 use std::sync::mpsc::Receiver;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::io::{self, BufRead};
+use std::sync::atomic::Ordering;
+use std::io::{self, BufRead, Write};
 use std::path::Path;
 use std::path::PathBuf;
 use std::collections::HashMap;
+use std::fs;
 use std::fs::File;
+use std::env;
+use std::thread;
+#[cfg(not(target_os = "macos"))]
+use std::panic;
 
 #[cfg(not(target_os = "macos"))]
 use winit::event_loop::EventLoop;
-use image::{DynamicImage};
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, RgbaImage};
+use image::codecs::png::PngDecoder;
 
-use minifb::{Scale, Window, WindowOptions};
-use raqote::{DrawOptions, DrawTarget, Image, PathBuilder, Point, SolidSource, Source, Transform, ExtendMode, FilterMode};
+use minifb::{Key, Scale, Window, WindowOptions};
+use raqote::{DrawOptions, DrawTarget, Image, PathBuilder, Point, SolidSource, Source, Transform, ExtendMode, FilterMode, Gradient, GradientStop, Color, Spread};
 use font_kit::loaders::default::Font;
+use font_kit::family_name::FamilyName;
+use font_kit::properties::Properties;
+use font_kit::source::SystemSource;
 use euclid::vec2;
 use sys_locale::get_locale;
+use crate::clock::{Clock, SystemClock};
 use crate::errors::*;
-use crate::ui::{Message, MAX_DOWNLOAD_PROGRESS};
+use crate::ui::{DownloadProgress, Message, VerificationProgress, MAX_DOWNLOAD_PROGRESS};
+use log::*;
+
+/// Size and scale factor of the primary screen with the bounds (x, y, width, height) of all monitors
+type Screen = (i32, i32, f64, Vec<(i32, i32, i32, i32)>);
+/// winit only supports a single event loop per process, so the screen is probed once for splash and retry dialog
+static SCREEN: OnceLock<Option<Screen>> = OnceLock::new();
+/// Dark theme and high contrast mode of the desktop, detected once for splash and retry dialog
+type Appearance = (bool, bool);
+static APPEARANCE: OnceLock<Appearance> = OnceLock::new();
+/// receives the appearance from the background detection until it is stored in `APPEARANCE`
+static APPEARANCE_PROBE: Mutex<Option<Receiver<Appearance>>> = Mutex::new(None);
+/// the detection may start external tools, the default appearance is used if they do not answer in time
+const APPEARANCE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Environment variable to disable the splash window, e.g. on machines without a display
+const NO_SPLASH_ENV: &str = "NATIVESTART_NO_SPLASH";
+const STRINGS_FILE_NAME: &str = "strings.toml";
+/// density buckets with their scale, authors provide images for the buckets up to the one given in the splash header
+const DPI_BUCKETS: [(&str, f64); 5] = [("mdpi", 1.0), ("hdpi", 1.5), ("xhdpi", 2.0), ("xxhdpi", 3.0), ("xxxhdpi", 4.0)];
+const DEFAULT_MAX_DPI: &str = "xhdpi";
+const STATUS_DOWNLOADING: &str = "downloading";
+const STATUS_EXTRACTING: &str = "extracting";
+const STATUS_VERIFYING: &str = "verifying";
+const STATUS_STARTING: &str = "starting";
+const NOTICE_OFFLINE: &str = "offline";
+/// frame rates of the splash window, lower rates save energy for static splash screens
+const DEFAULT_FRAME_RATE: usize = 30;
+const ANIMATION_FRAME_RATE: usize = 60;
+/// keeps the progress bar responsive
+const MIN_FRAME_RATE: usize = 20;
+const MAX_FRAME_RATE: usize = 120;
+/// delay in milliseconds used for frames of animated images without delay
+const DEFAULT_FRAME_DELAY: u64 = 100;
+/// splash screen used if the application has no splash screen or it cannot be parsed:
+/// the application name with an indeterminate progress bar, which shows the download progress if available
+const BUILTIN_SPLASH: &str = "
+splash 360 140
+
+[background]
+gradient 0 0 245 245 245 1 0 245 245 245
+textfont family sans-serif
+textalign center
+textsize 22
+fill 32 32 32
+filltext 180 62 ${name}
+textsize 12
+fill 96 96 96
+filltext 180 88 ${status}
+gradient 0 0 210 210 210 1 0 210 210 210 40 110 280 4
+gradient 0 0 60 120 200 1 0 60 120 200 40+200*(0.5+0.5*sin(${elapsed}*3)) 110 80 4
+
+[progress]
+gradient 0 0 210 210 210 1 0 210 210 210 40 110 280 4
+gradient 0 0 60 120 200 1 0 60 120 200 40 110 280*${progress} 4
+
+[background.contrast]
+gradient 0 0 0 0 0 1 0 0 0 0
+textfont family sans-serif
+textalign center
+textsize 22
+fill 255 255 255
+filltext 180 62 ${name}
+textsize 12
+filltext 180 88 ${status}
+gradient 0 0 128 128 128 1 0 128 128 128 40 110 280 4
+gradient 0 0 255 255 0 1 0 255 255 0 40+200*(0.5+0.5*sin(${elapsed}*3)) 110 80 4
+
+[progress.contrast]
+gradient 0 0 128 128 128 1 0 128 128 128 40 110 280 4
+gradient 0 0 255 255 0 1 0 255 255 0 40 110 280*${progress} 4
+";
+
+/// dialog shown for transient errors (e.g. network failures) to retry the launch instead of quitting
+const RETRY_DIALOG: &str = "
+splash 420 180
+
+[background]
+gradient 0 0 245 245 245 1 0 245 245 245
+textfont family sans-serif
+textalign center
+textsize 16
+fill 32 32 32
+filltext 210 36 ${name}
+textsize 12
+fill 160 32 32
+wraptext 210 66 380 ${message}
+fill 96 96 96
+filltext 210 160 ${hint}
+
+[background.contrast]
+gradient 0 0 0 0 0 1 0 0 0 0
+textfont family sans-serif
+textalign center
+textsize 16
+fill 255 255 255
+filltext 210 36 ${name}
+textsize 12
+fill 255 255 0
+wraptext 210 66 380 ${message}
+fill 255 255 255
+filltext 210 160 ${hint}
+";
+const RETRY_HINT: &str = "Press Enter to retry or Escape to quit";
 
 macro_rules! parse {
     ( $cmd:expr, $( $x:expr ),* ) => {
@@ -26,9 +139,9 @@ macro_rules! parse {
             let mut index = 0;
             $(
                 index = index + 1;
-                $x = $cmd[index].parse::<>().expect(
-                    format!("parameter {} of command {} has wrong type", index, $cmd[0]).as_str()
-                );
+                $x = $cmd.get(index).and_then(|value| value.parse::<>().ok()).ok_or_else(|| ErrorKind::SplashError(
+                    format!("parameter {} of command {} is missing or has wrong type", index, $cmd[0])
+                ))?;
             )*
         }
     };
@@ -37,14 +150,59 @@ macro_rules! parse {
 pub struct Splash {
     app_name: &'static str,
     version: String,
-    image_path: PathBuf,
+    image_path: Option<PathBuf>,
+    position_file: PathBuf,
+    clock: Arc<dyn Clock>,
 }
 
 struct SplashImpl {
     width: usize,
     height: usize,
+    /// highest density bucket the splash provides images for
+    max_dpi: String,
+    /// frame rate given with `framerate`, by default it depends on the use of animations
+    frame_rate: Option<usize>,
+    /// durations of fade-in and fade-out in milliseconds given with `fade`
+    fade: Option<(u64, u64)>,
     background: Vec<Vec<String>>,
-    progress: Vec<Vec<String>>
+    progress: Vec<Vec<String>>,
+    /// alternative commands used in high contrast mode, empty if not provided
+    contrast_background: Vec<Vec<String>>,
+    contrast_progress: Vec<Vec<String>>
+}
+
+impl SplashImpl {
+    /// Replaces the commands by the high contrast alternatives provided by the splash file
+    fn use_high_contrast(&mut self) {
+        if !self.contrast_background.is_empty() {
+            self.background = std::mem::take(&mut self.contrast_background);
+        }
+        if !self.contrast_progress.is_empty() {
+            self.progress = std::mem::take(&mut self.contrast_progress);
+        }
+    }
+
+    /// Returns true if the splash contains animations or uses the time based placeholders
+    fn is_animated(&self) -> bool {
+        return self.background.iter().chain(self.progress.iter()).any(|tokens| tokens[0] == "animation"
+            || tokens.iter().any(|token| token.contains("${elapsed}") || token.contains("${frame}")));
+    }
+}
+
+/// Result of the `[background]` commands, reused as long as the placeholders they reference are unchanged
+struct BackgroundCache {
+    key: Vec<Option<String>>,
+    data: Vec<u32>,
+    fill: (u8, u8, u8, u8),
+    text_font: Option<Font>,
+    text_size: f32,
+    text_align: f32,
+    text_leading: Option<f32>,
+}
+
+/// Frames of an animated image with the time in milliseconds at which each frame ends, the animation loops forever
+struct AnimatedImage {
+    frames: Vec<(u64, Vec<u32>)>,
 }
 
 struct DrawContext {
@@ -53,32 +211,78 @@ struct DrawContext {
     text_font: Option<Font>,
     text_size: f32,
     text_align: f32,
+    /// distance between the baselines of multiple lines, proportional to the text size if not given
+    text_leading: Option<f32>,
+    /// number of clip regions pushed with `clip` and not yet ended
+    clips: usize,
 
     basedir: PathBuf,
     images: HashMap<String, (u32, u32, Vec<u32>)>,
+    animations: HashMap<String, AnimatedImage>,
+    /// set if an animated image was drawn, so that the result depends on the elapsed time
+    animated: bool,
     placeholders: HashMap<String, String>,
+    clock: Arc<dyn Clock>,
+    start: Instant,
 
     draw_target: DrawTarget
 }
 
 impl Splash {
-    pub fn new(app_name: &'static str, version: String, image_dir: PathBuf) -> Splash {
+    pub fn new(app_name: &'static str, version: String, image_dir: Option<PathBuf>, position_file: PathBuf) -> Splash {
         return Splash {
             app_name,
             version,
             image_path: image_dir,
+            position_file,
+            clock: Arc::new(SystemClock),
         };
     }
     pub fn show_and_await_termination(&mut self, rx: Receiver<Message>) -> Result<()> {
-        let (screen_width, screen_height, screen_scale, img_scale, dpi) = Splash::get_screen_size();
+        if Splash::is_disabled() {
+            info!("Splash screen disabled by {}", NO_SPLASH_ENV);
+            Splash::log_and_await_termination(&self.app_name, rx);
+            return Ok(());
+        }
+
+        let (screen_width, screen_height, scale_factor, monitors) = match Splash::get_screen_size() {
+            Some(screen_size) => screen_size,
+            None => {
+                warn!("No monitor detected, splash screen disabled");
+                Splash::log_and_await_termination(&self.app_name, rx);
+                return Ok(());
+            }
+        };
 
-        let splash = Splash::parse_splash(&self.image_path);
+        let mut splash = match &self.image_path {
+            Some(image_path) => match Splash::parse_splash(image_path) {
+                Ok(splash) => splash,
+                Err(e) => {
+                    error!("Invalid splash screen, using built-in splash screen: {}", e);
+                    self.image_path = None;
+                    Splash::builtin_splash()
+                }
+            },
+            None => {
+                info!("No valid splash screen available, using built-in splash screen");
+                Splash::builtin_splash()
+            }
+        };
+        let (dark_theme, high_contrast) = Splash::get_appearance();
+        if high_contrast {
+            splash.use_high_contrast();
+        }
+        let (img_scale, dpi) = Splash::map_scale(scale_factor, &splash.max_dpi);
+        // MacOS uses logical coordinates for window size and positioning, not physical
+        let screen_scale = if cfg!(target_os = "macos") { 1.0 } else { img_scale };
         let window_width = (splash.width as f64 * screen_scale) as usize;
         let window_height = (splash.height as f64 * screen_scale) as usize;
         let img_width = (splash.width as f64 * img_scale) as usize;
         let img_height = (splash.height as f64 * img_scale) as usize;
+        // transparent windows are not supported by minifb on macOS
+        let fade = if cfg!(target_os = "macos") { None } else { splash.fade };
 
-        let mut window = Window::new(
+        let window = Window::new(
             self.app_name,
             window_width,
             window_height,
@@ -88,55 +292,117 @@ impl Splash {
                 resize: false,
                 scale: Scale::X1,
                 none: true,
+                transparency: fade.is_some(),
                 ..WindowOptions::default()
             },
-        ).expect("failed to create window");
-        window.set_position(((screen_width - window_width as i32) / 2) as isize, ((screen_height - window_height as i32) / 2) as isize);
+        );
+        let mut window = match window {
+            Ok(window) => window,
+            Err(e) => {
+                warn!("Could not create splash window, splash screen disabled: {}", e);
+                Splash::log_and_await_termination(&self.app_name, rx);
+                return Ok(());
+            }
+        };
+        match Splash::read_position(&self.position_file) {
+            Some((x, y)) if Splash::is_on_monitor(x, y, &monitors) => {
+                window.set_position(x as isize, y as isize);
+            }
+            _ => {
+                window.set_position(((screen_width - window_width as i32) / 2) as isize, ((screen_height - window_height as i32) / 2) as isize);
+            }
+        }
 
         let mut placeholders = HashMap::new();
         placeholders.insert(String::from("dpi"), dpi);
+        placeholders.insert(String::from("scale_factor"), scale_factor.to_string());
         placeholders.insert(String::from("version"), String::from(&self.version));
+        placeholders.insert(String::from("name"), String::from(self.app_name));
         let locale = get_locale().unwrap_or_else(|| String::from(""));
+        let status_labels = Splash::load_status_labels(self.image_path.as_ref(), &locale);
         placeholders.insert(String::from("locale"), locale);
+        placeholders.insert(String::from("theme"), String::from(if dark_theme { "dark" } else { "light" }));
+        placeholders.insert(String::from("dark"), String::from(if dark_theme { "1" } else { "0" }));
+        placeholders.insert(String::from("contrast"), String::from(if high_contrast { "1" } else { "0" }));
+        placeholders.insert(String::from("offline"), String::from("0"));
+        placeholders.insert(String::from("notice"), String::new());
 
-        let mut draw_context = DrawContext {
-            scale: img_scale,
-            fill: (0, 0, 0, 255),
-            text_font: None,
-            text_size: 12.0,
-            text_align: 0.0,
-            basedir: self.image_path.clone(),
-            images: HashMap::new(),
-            placeholders,
-
-            draw_target: DrawTarget::new(img_width as i32, img_height as i32)
-        };
-
-        for tokens in &splash.background {
-            draw_context = Splash::execute_command(tokens, draw_context);
-        }
+        let mut draw_context = DrawContext::new(img_scale, self.image_path.clone().unwrap_or_default(), placeholders,
+                                                self.clock.clone(), img_width, img_height);
 
-        let mut cur_progress: Option<Arc<AtomicUsize>> = None;
-        let mut status = "";
+        let background_placeholders = Splash::referenced_placeholders(&splash.background);
+        let mut background_cache: Option<BackgroundCache> = None;
+        let mut cur_progress: Option<Arc<DownloadProgress>> = None;
+        let mut app_progress: Option<f64> = None;
+        let mut verification_progress: Option<Arc<VerificationProgress>> = None;
+        let mut status = String::new();
         let mut exit_loop = false;
-        window.set_target_fps(60);
+        let mut ui_visible = false;
+        let mut terminated = false;
+        let shown = self.clock.now();
+        let mut closing: Option<Instant> = None;
+        let has_animation = splash.is_animated();
+        let mut frame: u64 = 0;
         loop {
-            draw_context.placeholders.insert(String::from("status"), String::from(status));
-            for tokens in &splash.background {
-                draw_context = Splash::execute_command(tokens, draw_context);
+            let frame_rate = splash.frame_rate.unwrap_or(
+                if has_animation || !draw_context.animations.is_empty() { ANIMATION_FRAME_RATE } else { DEFAULT_FRAME_RATE });
+            window.set_target_fps(frame_rate);
+            draw_context.placeholders.insert(String::from("elapsed"), self.clock.elapsed(shown).as_secs_f64().to_string());
+            draw_context.placeholders.insert(String::from("frame"), frame.to_string());
+            frame = frame + 1;
+            if let Some(progress_state) = &cur_progress {
+                let extracting = progress_state.extracting.load(Ordering::SeqCst);
+                status = String::from(if extracting { STATUS_EXTRACTING } else { STATUS_DOWNLOADING });
+            }
+            let status_label = status_labels.get(&status).unwrap_or(&status);
+            draw_context.placeholders.insert(String::from("status"), status_label.clone());
+            let progress = match &verification_progress {
+                Some(verification_progress) => Some(verification_progress.progress.load(Ordering::SeqCst) as f64 / MAX_DOWNLOAD_PROGRESS as f64),
+                None => app_progress
+            };
+            draw_context = match Splash::draw_frame(&splash, &background_placeholders, &mut background_cache, &cur_progress, progress, draw_context) {
+                Ok(draw_context) => draw_context,
+                Err(e) => {
+                    // a broken splash screen must not prevent the application from starting
+                    error!("Could not draw splash screen, splash screen disabled: {}", e);
+                    drop(window);
+                    Splash::log_and_await_termination(&self.app_name, rx);
+                    return Ok(());
+                }
+            };
+
+            let alpha = match fade {
+                Some(fade) => Splash::fade_alpha(fade, self.clock.elapsed(shown), closing.map(|closing| self.clock.elapsed(closing))),
+                None => 1.0
+            };
+            if alpha < 1.0 {
+                let buffer: Vec<u32> = draw_context.draw_target.get_data().iter().map(|pixel| Splash::fade_pixel(*pixel, alpha)).collect();
+                window.update_with_buffer(&buffer, img_width, img_height).unwrap();
+            } else {
+                window.update_with_buffer(draw_context.draw_target.get_data(), img_width, img_height).unwrap();
             }
 
             if let Some(progress) = &cur_progress {
-                let progress = progress.load(Ordering::SeqCst) as f64 / MAX_DOWNLOAD_PROGRESS as f64;
-                draw_context.placeholders.insert(String::from("progress"),progress.to_string());
-                for tokens in &splash.progress {
-                    draw_context = Splash::execute_command(tokens, draw_context);
+                if window.is_key_down(Key::Escape) && !progress.cancelled.load(Ordering::SeqCst) {
+                    info!("Cancelling download");
+                    progress.cancelled.store(true, Ordering::SeqCst);
+                }
+            }
+            if let Some(progress) = &verification_progress {
+                if window.is_key_down(Key::Escape) && !progress.cancelled.load(Ordering::SeqCst) {
+                    info!("Cancelling verification");
+                    progress.cancelled.store(true, Ordering::SeqCst);
                 }
             }
-
-            window.update_with_buffer(draw_context.draw_target.get_data(), img_width, img_height).unwrap();
 
             if exit_loop {
+                if let Some((_, fade_out)) = fade {
+                    // keep drawing without handling messages until the window is faded out
+                    let closing = *closing.get_or_insert_with(|| self.clock.now());
+                    if self.clock.elapsed(closing) < Duration::from_millis(fade_out) {
+                        continue;
+                    }
+                }
                 // exit loop after UI has been redrawn
                 break;
             }
@@ -144,27 +410,312 @@ impl Splash {
                 Ok(Message::Error(val)) => {
                     crate::show_error_message(&self.app_name, val, true);
                 },
+                Ok(Message::RetryableError(val, retry)) => {
+                    crate::ask_retry(&self.app_name, val, retry);
+                },
+                Ok(Message::Offline) => {
+                    draw_context.placeholders.insert(String::from("offline"), String::from("1"));
+                    draw_context.placeholders.insert(String::from("notice"), status_labels.get(NOTICE_OFFLINE).cloned().unwrap_or_default());
+                },
                 Ok(Message::Downloading(val)) => {
-                    status = "Downloading";
+                    status = String::from(STATUS_DOWNLOADING);
                     cur_progress = Some(val);
+                    verification_progress = None;
+                },
+                Ok(Message::Verifying(val)) => {
+                    status = String::from(STATUS_VERIFYING);
+                    cur_progress = None;
+                    verification_progress = Some(val);
                 },
-                Ok(Message::FilesReady) | Err(mpsc::RecvTimeoutError::Disconnected) => {
-                    status = "Starting";
+                Ok(Message::FilesReady) => {
+                    status = String::from(STATUS_STARTING);
                     cur_progress = None;
+                    verification_progress = None;
+                    // macOS requires the main thread to run the application loop once Java gets started
+                    exit_loop = cfg!(target_os = "macos");
+                },
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    status = String::from(STATUS_STARTING);
+                    cur_progress = None;
+                    exit_loop = true;
+                },
+                Ok(Message::ApplicationProgress(progress, application_status)) => {
+                    app_progress = Some(progress);
+                    if let Some(application_status) = application_status {
+                        status = application_status;
+                    }
+                },
+                Ok(Message::ApplicationStatus(application_status)) => {
+                    status = application_status;
+                },
+                Ok(Message::ApplicationUiVisible) => {
+                    ui_visible = true;
+                    exit_loop = true;
+                },
+                Ok(Message::ApplicationTerminated) => {
+                    terminated = true;
                     exit_loop = true;
                 },
                 Ok(_) | Err(mpsc::RecvTimeoutError::Timeout) => ()
             }
         }
 
-        Splash::await_termination(&self.app_name, rx, window);
+        let (x, y) = window.get_position();
+        Splash::write_position(&self.position_file, x as i32, y as i32);
+
+        if terminated {
+            return Ok(());
+        }
+        Splash::await_termination(&self.app_name, rx, window, ui_visible);
 
         return Ok(());
     }
 
+    /// Shows the message of a transient error in a small dialog. Returns true if the user wants to retry (Enter)
+    /// and false if the user wants to quit (Escape or closing the dialog), `None` if the dialog cannot be shown.
+    pub fn show_retry_dialog(app_name: &'static str, message: &str) -> Option<bool> {
+        if Splash::is_disabled() {
+            return None;
+        }
+        let (screen_width, screen_height, scale_factor, _) = Splash::get_screen_size()?;
+        let mut dialog = Splash::retry_dialog();
+        let (_, high_contrast) = Splash::get_appearance();
+        if high_contrast {
+            dialog.use_high_contrast();
+        }
+        let (img_scale, dpi) = Splash::map_scale(scale_factor, &dialog.max_dpi);
+        // MacOS uses logical coordinates for window size and positioning, not physical
+        let screen_scale = if cfg!(target_os = "macos") { 1.0 } else { img_scale };
+        let window_width = (dialog.width as f64 * screen_scale) as usize;
+        let window_height = (dialog.height as f64 * screen_scale) as usize;
+        let img_width = (dialog.width as f64 * img_scale) as usize;
+        let img_height = (dialog.height as f64 * img_scale) as usize;
+
+        let window = Window::new(
+            app_name,
+            window_width,
+            window_height,
+            WindowOptions {
+                resize: false,
+                scale: Scale::X1,
+                ..WindowOptions::default()
+            },
+        );
+        let mut window = match window {
+            Ok(window) => window,
+            Err(e) => {
+                warn!("Could not create retry dialog: {}", e);
+                return None;
+            }
+        };
+        window.set_position(((screen_width - window_width as i32) / 2) as isize, ((screen_height - window_height as i32) / 2) as isize);
+        window.set_target_fps(DEFAULT_FRAME_RATE);
+
+        let mut placeholders = HashMap::new();
+        placeholders.insert(String::from("dpi"), dpi);
+        placeholders.insert(String::from("name"), String::from(app_name));
+        placeholders.insert(String::from("message"), String::from(message));
+        placeholders.insert(String::from("hint"), String::from(RETRY_HINT));
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let draw_context = DrawContext::new(img_scale, PathBuf::new(), placeholders, clock, img_width, img_height);
+        let draw_context = match Splash::execute_commands(&dialog.background, draw_context) {
+            Ok(draw_context) => draw_context,
+            Err(e) => {
+                error!("Could not draw retry dialog: {}", e);
+                return None;
+            }
+        };
+
+        while window.is_open() {
+            window.update_with_buffer(draw_context.draw_target.get_data(), img_width, img_height).unwrap();
+            if window.is_key_down(Key::Enter) {
+                info!("Retry requested by user");
+                return Some(true);
+            }
+            if window.is_key_down(Key::Escape) {
+                break;
+            }
+        }
+        return Some(false);
+    }
+
+    /// Loads the status labels for the given locale from the optional `strings.toml` in the splash directory.
+    /// The file contains a table per language (e.g. `[de]` or `[de-CH]`) mapping status keys to labels.
+    /// English labels are used for all statuses without translation.
+    fn load_status_labels(splash_dir: Option<&PathBuf>, locale: &str) -> HashMap<String, String> {
+        let mut labels = HashMap::new();
+        labels.insert(String::from(STATUS_DOWNLOADING), String::from("Downloading"));
+        labels.insert(String::from(STATUS_EXTRACTING), String::from("Extracting"));
+        labels.insert(String::from(STATUS_VERIFYING), String::from("Verifying"));
+        labels.insert(String::from(STATUS_STARTING), String::from("Starting"));
+        labels.insert(String::from(NOTICE_OFFLINE), String::from("Running offline, update pending"));
+
+        let path = match splash_dir {
+            Some(splash_dir) => splash_dir.join(STRINGS_FILE_NAME),
+            None => return labels
+        };
+        if !path.exists() {
+            return labels;
+        }
+        let translations: HashMap<String, HashMap<String, String>> = match fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| toml::from_str(&content).map_err(|e| e.to_string())) {
+            Ok(translations) => translations,
+            Err(e) => {
+                warn!("Could not read status labels from {:?}: {}", &path, e);
+                return labels;
+            }
+        };
+
+        // more specific locales (e.g. de-CH) take precedence over the language (e.g. de)
+        let language = locale.split(|c| c == '-' || c == '_').next().unwrap_or("");
+        for key in [language, locale] {
+            if let Some(translation) = translations.get(key) {
+                labels.extend(translation.clone());
+            }
+        }
+        return labels;
+    }
+
+    fn format_mb(bytes: u64) -> String {
+        return format!("{:.1}", bytes as f64 / (1024 * 1024) as f64);
+    }
+
+    /// Formats the download rate (e.g. `12.4 MB/s`) and the remaining time (e.g. `30s`). Empty if the rate is unknown.
+    fn format_speed(bytes_per_second: u64, remaining_bytes: u64) -> (String, String) {
+        if bytes_per_second == 0 {
+            return (String::new(), String::new());
+        }
+        let speed = format!("{} MB/s", Splash::format_mb(bytes_per_second));
+        let seconds = remaining_bytes.div_ceil(bytes_per_second);
+        let eta = if seconds < 60 { format!("{}s", seconds) } else { format!("{}m {}s", seconds / 60, seconds % 60) };
+        return (speed, eta);
+    }
+
+    /// Returns the opacity of the window for the given time since showing and closing it
+    fn fade_alpha(fade: (u64, u64), shown: Duration, closing: Option<Duration>) -> f64 {
+        let (fade_in, fade_out) = fade;
+        let ratio = |elapsed: Duration, duration: u64| if duration > 0 {
+            (elapsed.as_millis() as f64 / duration as f64).min(1.0)
+        } else { 1.0 };
+        return ratio(shown, fade_in) * closing.map(|closing| 1.0 - ratio(closing, fade_out)).unwrap_or(1.0);
+    }
+
+    /// Draws the background and, while a download or the application start is in progress, the progress commands
+    fn draw_frame(splash: &SplashImpl, background_placeholders: &Option<Vec<String>>, background_cache: &mut Option<BackgroundCache>,
+                  cur_progress: &Option<Arc<DownloadProgress>>, progress: Option<f64>, mut draw_context: DrawContext) -> Result<DrawContext> {
+        draw_context = Splash::draw_background(&splash.background, background_placeholders, background_cache, draw_context)?;
+
+        if let Some(progress_state) = cur_progress {
+            let downloaded_bytes = progress_state.downloaded_bytes.load(Ordering::SeqCst);
+            let total_bytes = progress_state.total_bytes.load(Ordering::SeqCst);
+            let progress = progress_state.progress.load(Ordering::SeqCst) as f64 / MAX_DOWNLOAD_PROGRESS as f64;
+            draw_context.placeholders.insert(String::from("progress"),progress.to_string());
+            draw_context.placeholders.insert(String::from("percent"), ((progress * 100.0).round() as u32).to_string());
+            draw_context.placeholders.insert(String::from("downloaded_mb"), Splash::format_mb(downloaded_bytes));
+            draw_context.placeholders.insert(String::from("total_mb"), Splash::format_mb(total_bytes));
+            let (speed, eta) = Splash::format_speed(progress_state.bytes_per_second.load(Ordering::SeqCst), total_bytes.saturating_sub(downloaded_bytes));
+            draw_context.placeholders.insert(String::from("speed"), speed);
+            draw_context.placeholders.insert(String::from("eta"), eta);
+            let file_total_bytes = progress_state.file_total_bytes.load(Ordering::SeqCst);
+            let file_progress = if file_total_bytes > 0 {
+                (progress_state.file_downloaded_bytes.load(Ordering::SeqCst) as f64 / file_total_bytes as f64).min(1.0)
+            } else { 0.0 };
+            draw_context.placeholders.insert(String::from("file"), progress_state.file_index.load(Ordering::SeqCst).to_string());
+            draw_context.placeholders.insert(String::from("file_count"), progress_state.file_count.load(Ordering::SeqCst).to_string());
+            draw_context.placeholders.insert(String::from("file_progress"), file_progress.to_string());
+            draw_context = Splash::execute_commands(&splash.progress, draw_context)?;
+        } else if let Some(progress) = progress {
+            // progress of the verification or reported by the Java application during its initialization
+            draw_context.placeholders.insert(String::from("progress"),progress.to_string());
+            draw_context.placeholders.insert(String::from("percent"), ((progress * 100.0).round() as u32).to_string());
+            draw_context = Splash::execute_commands(&splash.progress, draw_context)?;
+        }
+        return Ok(draw_context);
+    }
+
+    /// Applies the given opacity to a premultiplied ARGB pixel
+    fn fade_pixel(pixel: u32, alpha: f64) -> u32 {
+        let channel = |shift: u32| ((((pixel >> shift) & 0xFF) as f64 * alpha).round() as u32) << shift;
+        return channel(24) | channel(16) | channel(8) | channel(0);
+    }
+
+    fn is_disabled() -> bool {
+        return env::var(NO_SPLASH_ENV).map(|value| value == "1" || value == "true").unwrap_or(false);
+    }
+
+    /// Replacement for the splash window if no display is available: progress is written to the console
+    fn log_and_await_termination(app_name: &'static str, rx: Receiver<Message>) {
+        let mut cur_progress: Option<Arc<DownloadProgress>> = None;
+        let mut last_percent: Option<usize> = None;
+        let mut last_status: Option<String> = None;
+        let mut console = crate::ui::console_output();
+        loop {
+            if let Some(progress) = &cur_progress {
+                let percent = progress.progress.load(Ordering::SeqCst) * 100 / MAX_DOWNLOAD_PROGRESS;
+                // only report every 10 percent to keep the output readable
+                if percent <= 100 && last_percent.map_or(true, |last| percent / 10 != last / 10) {
+                    let _ = writeln!(console, "Downloading: {}%", percent);
+                    last_percent = Some(percent);
+                }
+            }
+            match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(Message::Error(val)) => {
+                    // the console replaces the error dialog
+                    let _ = writeln!(console, "{}", val);
+                    std::process::exit(1);
+                },
+                Ok(Message::RetryableError(val, retry)) => {
+                    crate::ask_retry(app_name, val, retry);
+                },
+                Ok(Message::Offline) => {
+                    let _ = writeln!(console, "Running offline, update pending");
+                },
+                Ok(Message::Downloading(val)) => {
+                    let _ = writeln!(console, "Downloading");
+                    cur_progress = Some(val);
+                    last_percent = None;
+                },
+                Ok(Message::Verifying(_)) => {
+                    let _ = writeln!(console, "Verifying");
+                    cur_progress = None;
+                },
+                Ok(Message::FilesReady) => {
+                    let _ = writeln!(console, "Starting");
+                    cur_progress = None;
+                },
+                Ok(Message::ApplicationProgress(progress, status)) => {
+                    let percent = (progress * 100.0).round() as usize;
+                    if status.is_some() && status != last_status {
+                        last_status = status;
+                        last_percent = None;
+                    }
+                    if last_percent.map_or(true, |last| percent / 10 != last / 10) {
+                        let _ = writeln!(console, "{}: {}%", last_status.as_deref().unwrap_or("Starting"), percent);
+                        last_percent = Some(percent);
+                    }
+                },
+                Ok(Message::ApplicationStatus(status)) => {
+                    if Some(&status) != last_status.as_ref() {
+                        let _ = writeln!(console, "{}", status);
+                        last_status = Some(status);
+                    }
+                },
+                Ok(Message::ApplicationTerminated) | Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    break;
+                },
+                Ok(_) | Err(mpsc::RecvTimeoutError::Timeout) => ()
+            }
+        }
+    }
+
     #[cfg(not(target_os = "macos"))]
-    fn await_termination(app_name: &'static str, rx: Receiver<Message>, window: Window) {
+    fn await_termination(app_name: &'static str, rx: Receiver<Message>, window: Window, ui_visible: bool) {
         let mut win = Some(window);
+        if ui_visible {
+            drop(win); // close window
+            win = None;
+        }
         loop {
             match rx.recv() {
                 Ok(Message::ApplicationUiVisible)  => {
@@ -183,7 +734,7 @@ impl Splash {
     }
 
     #[cfg(target_os = "macos")]
-    fn await_termination(app_name: &'static str, rx: Receiver<Message>, window: Window) {
+    fn await_termination(app_name: &'static str, rx: Receiver<Message>, window: Window, _ui_visible: bool) {
         use std::thread;
         use std::process::exit;
         use send_wrapper::SendWrapper;
@@ -233,95 +784,315 @@ impl Splash {
         }
     }
 
+    fn read_position(path: &PathBuf) -> Option<(i32, i32)> {
+        let content = fs::read_to_string(path).ok()?;
+        let mut coordinates = content.split_whitespace().map(|value| value.parse::<i32>());
+        return match (coordinates.next(), coordinates.next()) {
+            (Some(Ok(x)), Some(Ok(y))) => Some((x, y)),
+            _ => None
+        };
+    }
+
+    fn write_position(path: &PathBuf, x: i32, y: i32) {
+        if let Err(e) = fs::write(path, format!("{} {}", x, y)) {
+            warn!("Could not store splash position in {:?}: {}", path, e);
+        }
+    }
+
+    /// Checks if the given position is on one of the given monitors (x, y, width, height)
+    fn is_on_monitor(x: i32, y: i32, monitors: &Vec<(i32, i32, i32, i32)>) -> bool {
+        return monitors.iter().any(|(monitor_x, monitor_y, width, height)| {
+            x >= *monitor_x && x < monitor_x + width && y >= *monitor_y && y < monitor_y + height
+        });
+    }
+
+    fn get_screen_size() -> Option<Screen> {
+        return SCREEN.get_or_init(Splash::probe_screen).clone();
+    }
+
     #[cfg(not(target_os = "macos"))]
-    fn get_screen_size() -> (i32, i32, f64, f64, String) {
-        let events_loop = EventLoop::new();
-        let monitor = events_loop.primary_monitor().unwrap();
+    fn probe_screen() -> Option<Screen> {
+        // winit 0.28 has no fallible event loop creation and panics if it cannot connect to a display server,
+        // the cause is logged instead of printing the panic to stderr
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(|_| {}));
+        let events_loop = panic::catch_unwind(|| EventLoop::new());
+        panic::set_hook(hook);
+        let events_loop = match events_loop {
+            Ok(events_loop) => events_loop,
+            Err(cause) => {
+                let message = cause.downcast_ref::<String>().map(String::as_str)
+                    .or_else(|| cause.downcast_ref::<&str>().copied())
+                    .unwrap_or("unknown error");
+                warn!("Could not connect to the display server: {}", message);
+                return None;
+            }
+        };
+        // not all platforms (e.g. Wayland) know about a primary monitor
+        let monitor = events_loop.primary_monitor()
+            .or_else(|| events_loop.available_monitors().next())?;
         let factor = monitor.scale_factor();
         let width = monitor.size().width as i32;
         let height = monitor.size().height as i32;
-        let (factor, dpi) = Splash::map_scale(factor);
 
-        return (width, height, factor, factor, dpi);
+        let monitors = events_loop.available_monitors()
+            .map(|monitor| (monitor.position().x, monitor.position().y, monitor.size().width as i32, monitor.size().height as i32))
+            .collect();
+
+        return Some((width, height, factor, monitors));
     }
 
     #[cfg(target_os = "macos")]
-    fn get_screen_size() -> (i32, i32, f64, f64, String) {
+    fn probe_screen() -> Option<Screen> {
         // Use CoreGraphics directly instead of winit to avoid registering
         // stale run loop observers that crash when NSApp().run() is called later.
         use core_graphics::display::CGDisplay;
 
         let main_display = CGDisplay::main();
-        let mode = main_display.display_mode().expect("failed to get display mode");
+        // no display mode is available if no monitor is attached
+        let mode = main_display.display_mode()?;
         let bounds = main_display.bounds();
 
         let width = bounds.size.width as i32;
         let height = bounds.size.height as i32;
         let factor = mode.pixel_width() as f64 / bounds.size.width;
 
-        let (factor, dpi) = Splash::map_scale(factor);
+        let monitors = CGDisplay::active_displays().unwrap_or_default().into_iter()
+            .map(|id| CGDisplay::new(id).bounds())
+            .map(|bounds| (bounds.origin.x as i32, bounds.origin.y as i32, bounds.size.width as i32, bounds.size.height as i32))
+            .collect();
 
-        // MacOS uses logical coordinates for window size and positioning, not physical
-        return (width, height, 1.0, factor, dpi);
+        return Some((width, height, factor, monitors));
+    }
+
+    /// Starts detecting the appearance of the desktop in the background, so that it is known when the splash is shown
+    pub fn probe_appearance() {
+        let mut probe = APPEARANCE_PROBE.lock().unwrap();
+        if probe.is_none() && APPEARANCE.get().is_none() {
+            let (tx, rx) = mpsc::channel();
+            thread::spawn(move || {
+                let _ = tx.send((Splash::is_dark_theme(), Splash::is_high_contrast()));
+            });
+            *probe = Some(rx);
+        }
+    }
+
+    /// Returns if the dark theme and the high contrast mode are active, waits at most `APPEARANCE_TIMEOUT` for the detection
+    fn get_appearance() -> Appearance {
+        Splash::probe_appearance();
+        return *APPEARANCE.get_or_init(|| {
+            let probe = APPEARANCE_PROBE.lock().unwrap().take();
+            return match probe.map(|rx| rx.recv_timeout(APPEARANCE_TIMEOUT)) {
+                Some(Ok(appearance)) => appearance,
+                _ => {
+                    warn!("Could not detect the desktop theme within {:?}, using the light theme", APPEARANCE_TIMEOUT);
+                    (false, false)
+                }
+            };
+        });
+    }
+
+    #[cfg(target_os = "windows")]
+    fn is_dark_theme() -> bool {
+        use windows::core::w;
+        use windows::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD};
+
+        let mut light: u32 = 1;
+        let mut size = std::mem::size_of::<u32>() as u32;
+        let result = unsafe {
+            RegGetValueW(HKEY_CURRENT_USER,
+                         w!("Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize"),
+                         w!("AppsUseLightTheme"),
+                         RRF_RT_REG_DWORD,
+                         None,
+                         Some(&mut light as *mut u32 as *mut std::ffi::c_void),
+                         Some(&mut size))
+        };
+        return result.is_ok() && light == 0;
+    }
+
+    #[cfg(target_os = "macos")]
+    fn is_dark_theme() -> bool {
+        // the key only exists if dark mode is active
+        return std::process::Command::new("defaults")
+            .args(["read", "-g", "AppleInterfaceStyle"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim() == "Dark")
+            .unwrap_or(false);
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    fn is_dark_theme() -> bool {
+        if let Ok(theme) = env::var("GTK_THEME") {
+            return theme.to_lowercase().contains("dark");
+        }
+        return std::process::Command::new("gsettings")
+            .args(["get", "org.gnome.desktop.interface", "color-scheme"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).contains("dark"))
+            .unwrap_or(false);
+    }
+
+    #[cfg(target_os = "windows")]
+    fn is_high_contrast() -> bool {
+        use windows::core::w;
+        use windows::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_SZ};
+
+        // the flags are stored as decimal string, HCF_HIGHCONTRASTON is the lowest bit
+        let mut flags = [0u16; 16];
+        let mut size = std::mem::size_of_val(&flags) as u32;
+        let result = unsafe {
+            RegGetValueW(HKEY_CURRENT_USER,
+                         w!("Control Panel\\Accessibility\\HighContrast"),
+                         w!("Flags"),
+                         RRF_RT_REG_SZ,
+                         None,
+                         Some(flags.as_mut_ptr() as *mut std::ffi::c_void),
+                         Some(&mut size))
+        };
+        if result.is_err() {
+            return false;
+        }
+        let length = flags.iter().position(|c| *c == 0).unwrap_or(flags.len());
+        return String::from_utf16_lossy(&flags[..length]).trim().parse::<u32>().map(|flags| flags & 1 == 1).unwrap_or(false);
     }
 
-    fn map_scale(scale: f64) -> (f64, String) {
-        return if scale < 1.25 {
-            (1.0, String::from("mdpi"))
-        } else if scale < 1.75 {
-            (1.5, String::from("hdpi"))
-        } else {
-            (2.0, String::from("xhdpi"))
+    #[cfg(target_os = "macos")]
+    fn is_high_contrast() -> bool {
+        return std::process::Command::new("defaults")
+            .args(["read", "com.apple.universalaccess", "increaseContrast"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim() == "1")
+            .unwrap_or(false);
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    fn is_high_contrast() -> bool {
+        if let Ok(theme) = env::var("GTK_THEME") {
+            return theme.to_lowercase().contains("highcontrast");
+        }
+        return std::process::Command::new("gsettings")
+            .args(["get", "org.gnome.desktop.a11y.interface", "high-contrast"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim() == "true")
+            .unwrap_or(false);
+    }
+
+    /// Selects the nearest density bucket, but not higher than the given one
+    fn map_scale(scale: f64, max_dpi: &str) -> (f64, String) {
+        let mut selected = DPI_BUCKETS[0];
+        for bucket in &DPI_BUCKETS[1..] {
+            if selected.0 == max_dpi || scale < (selected.1 + bucket.1) / 2.0 {
+                break;
+            }
+            selected = *bucket;
         }
+        return (selected.1, String::from(selected.0));
+    }
+
+
+    fn parse_splash(splash_dir: &PathBuf) -> Result<SplashImpl> {
+        let mut path = splash_dir.clone();
+        path.push("splash");
+        let lines: Vec<String> = match Splash::read_lines(path) {
+            Ok(lines) => lines.filter_map(|line| line.ok()).collect(),
+            Err(_) => Vec::new()
+        };
+        return Splash::parse_splash_lines(&lines);
     }
 
+    fn builtin_splash() -> SplashImpl {
+        let lines: Vec<String> = BUILTIN_SPLASH.lines().map(String::from).collect();
+        return Splash::parse_splash_lines(&lines).expect("built-in splash screen is valid");
+    }
+
+    fn retry_dialog() -> SplashImpl {
+        let lines: Vec<String> = RETRY_DIALOG.lines().map(String::from).collect();
+        return Splash::parse_splash_lines(&lines).expect("retry dialog is valid");
+    }
 
-    fn parse_splash(splash_dir: &PathBuf) -> SplashImpl {
+    fn parse_splash_lines(lines: &Vec<String>) -> Result<SplashImpl> {
         let mut width: usize = 0;
         let mut height: usize = 0;
+        let mut max_dpi = String::from(DEFAULT_MAX_DPI);
+        let mut frame_rate: Option<usize> = None;
+        let mut fade: Option<(u64, u64)> = None;
         let mut background: Vec<Vec<String>> = Vec::new();
         let mut progress: Vec<Vec<String>> = Vec::new();
-        let mut is_background = true;
+        let mut contrast_background: Vec<Vec<String>> = Vec::new();
+        let mut contrast_progress: Vec<Vec<String>> = Vec::new();
+        let mut section = String::from("[background]");
 
-        let mut path = splash_dir.clone();
-        path.push("splash");
-        if let Ok(lines) = Splash::read_lines(path) {
-            for line in lines {
-                if let Ok(ln) = line {
-                    match ln.as_str() {
-                        "[background]" => {
-                            is_background = true;
-                        }
-                        "[progress]" => {
-                            is_background = false;
-                        }
-                        _ => {
-                            let tokens = ln
-                                .split_whitespace()
-                                .map(|token| token.to_string())
-                                .collect::<Vec<String>>();
-                            if tokens.len() > 0 {
-                                if tokens[0].eq("splash") {
-                                    parse!(tokens, width, height);
-                                } else {
-                                    if is_background {
-                                        background.push(tokens);
-                                    } else {
-                                        progress.push(tokens);
-                                    }
+        for ln in lines {
+            // tolerate CRLF line endings and indentation, skip blank and comment lines
+            let ln = ln.trim();
+            if ln.is_empty() || ln.starts_with("#") {
+                continue;
+            }
+            match ln {
+                "[background]" | "[progress]" | "[background.contrast]" | "[progress.contrast]" => {
+                    section = String::from(ln);
+                }
+                _ => {
+                    let tokens = ln
+                        .split_whitespace()
+                        .map(|token| token.to_string())
+                        .collect::<Vec<String>>();
+                    if tokens.len() > 0 {
+                        if tokens[0].eq("splash") {
+                            parse!(tokens, width, height);
+                            if let Some(dpi) = tokens.get(3) {
+                                if !DPI_BUCKETS.iter().any(|(name, _)| name == dpi) {
+                                    bail!(ErrorKind::SplashError(format!("unknown density {} in command splash", dpi)));
                                 }
+                                max_dpi = dpi.clone();
+                            }
+                        } else if tokens[0].eq("framerate") {
+                            let fps: usize;
+                            parse!(tokens, fps);
+                            frame_rate = Some(fps.clamp(MIN_FRAME_RATE, MAX_FRAME_RATE));
+                        } else if tokens[0].eq("fade") {
+                            let fade_in: u64;
+                            let fade_out: u64;
+                            parse!(tokens, fade_in, fade_out);
+                            fade = Some((fade_in, fade_out));
+                        } else {
+                            match section.as_str() {
+                                "[background]" => background.push(tokens),
+                                "[progress]" => progress.push(tokens),
+                                "[background.contrast]" => contrast_background.push(tokens),
+                                _ => contrast_progress.push(tokens)
                             }
                         }
                     }
                 }
             }
         }
-        return SplashImpl {
+        if width == 0 || height == 0 {
+            bail!(ErrorKind::SplashError(String::from("splash file is missing or has no size declaration 'splash <width> <height>'")));
+        }
+        return Ok(SplashImpl {
             width,
             height,
+            max_dpi,
+            frame_rate,
+            fade,
             background,
-            progress
+            progress,
+            contrast_background,
+            contrast_progress
+        });
+    }
+
+    /// Parses colors in the form `#RRGGBB` or `#RRGGBBAA`
+    fn parse_hex_color(color: &str) -> Option<(u8, u8, u8, u8)> {
+        let hex = color.strip_prefix("#")?;
+        if (hex.len() != 6 && hex.len() != 8) || !hex.is_ascii() {
+            return None;
         }
+        let channel = |index: usize| u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok();
+        let alpha = if hex.len() == 8 { channel(3)? } else { 255 };
+        return Some((channel(0)?, channel(1)?, channel(2)?, alpha));
     }
 
     fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
@@ -330,7 +1101,89 @@ impl Splash {
         Ok(io::BufReader::new(file).lines())
     }
 
-    fn execute_command(tokens: &Vec<String>, mut draw_context: DrawContext) -> DrawContext {
+    fn execute_commands(commands: &Vec<Vec<String>>, mut draw_context: DrawContext) -> Result<DrawContext> {
+        let clips = draw_context.clips;
+        for tokens in commands {
+            draw_context = Splash::execute_command(tokens, draw_context).map_err(|e| {
+                let reason = match e.kind() {
+                    ErrorKind::SplashError(msg) => msg.clone(),
+                    _ => e.to_string()
+                };
+                Error::with_chain(e, ErrorKind::SplashError(format!("{} (in line '{}')", reason, tokens.join(" "))))
+            })?;
+        }
+        // clip regions end with the commands they were defined in
+        while draw_context.clips > clips {
+            draw_context.draw_target.pop_clip();
+            draw_context.clips = draw_context.clips - 1;
+        }
+        return Ok(draw_context);
+    }
+
+    /// Returns the names of all placeholders used by the given commands or `None` if the commands
+    /// depend on the elapsed time (animations) and need to be executed for every frame.
+    fn referenced_placeholders(commands: &Vec<Vec<String>>) -> Option<Vec<String>> {
+        let mut names: Vec<String> = Vec::new();
+        for tokens in commands {
+            if tokens[0] == "animation" {
+                return None;
+            }
+            for token in tokens {
+                let mut rest = token.as_str();
+                while let Some(start) = rest.find("${") {
+                    rest = &rest[start + 2..];
+                    let end = match rest.find('}') {
+                        Some(end) => end,
+                        None => break
+                    };
+                    let name = String::from(&rest[..end]);
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                    rest = &rest[end + 1..];
+                }
+            }
+        }
+        return Some(names);
+    }
+
+    /// Draws the background, using the cached result of a previous frame if the referenced placeholders did not change
+    fn draw_background(commands: &Vec<Vec<String>>, placeholders: &Option<Vec<String>>, cache: &mut Option<BackgroundCache>, mut draw_context: DrawContext) -> Result<DrawContext> {
+        let placeholders = match placeholders {
+            Some(placeholders) => placeholders,
+            None => return Splash::execute_commands(commands, draw_context)
+        };
+        let key: Vec<Option<String>> = placeholders.iter().map(|name| draw_context.placeholders.get(name).cloned()).collect();
+        if let Some(cached) = cache {
+            if cached.key == key {
+                draw_context.draw_target.get_data_mut().copy_from_slice(&cached.data);
+                draw_context.fill = cached.fill;
+                draw_context.text_font = cached.text_font.clone();
+                draw_context.text_size = cached.text_size;
+                draw_context.text_align = cached.text_align;
+                draw_context.text_leading = cached.text_leading;
+                return Ok(draw_context);
+            }
+        }
+        draw_context.animated = false;
+        draw_context = Splash::execute_commands(commands, draw_context)?;
+        if draw_context.animated {
+            *cache = None;
+            return Ok(draw_context);
+        }
+        *cache = Some(BackgroundCache {
+            key,
+            data: draw_context.draw_target.get_data().to_vec(),
+            fill: draw_context.fill,
+            text_font: draw_context.text_font.clone(),
+            text_size: draw_context.text_size,
+            text_align: draw_context.text_align,
+            text_leading: draw_context.text_leading,
+        });
+        return Ok(draw_context);
+    }
+
+    fn execute_command(tokens: &Vec<String>, mut draw_context: DrawContext) -> Result<DrawContext> {
         match tokens[0].as_str() {
             "image" => {
                 let mut path: String;
@@ -354,46 +1207,27 @@ impl Splash {
                     src_y = String::from("0");
                 }
                 path = draw_context.eval_text(path);
-                let x = draw_context.eval_num(x) * draw_context.scale;
-                let y = draw_context.eval_num(y) * draw_context.scale;
-                let w = draw_context.eval_num(w) * draw_context.scale;
-                let h = draw_context.eval_num(h) * draw_context.scale;
-                let src_x = draw_context.eval_num(src_x) * draw_context.scale;
-                let src_y = draw_context.eval_num(src_y) * draw_context.scale;
-
-                if !draw_context.images.contains_key(path.as_str()) {
-                    for alternative in path.split(":") {
-                        let mut path_buffer = draw_context.basedir.clone();
-                        path_buffer.push(alternative);
-                        if !path_buffer.exists() {
-                            continue;
-                        }
-                        let img = image::open(path_buffer).unwrap();
-                        let img = match img {
-                            DynamicImage::ImageRgba8(img) => img,
-                            img => img.to_rgba8()
-                        };
-                        let width = img.dimensions().0;
-                        let height = img.dimensions().1;
-                        let mut buf: Vec<u32> = vec![0; (width * height) as usize];
-                        let mut i = 0;
-                        for p in img.pixels() {
-                            let alpha = p.0[3] as u32;
-                            let r = (p.0[0] as u32 * alpha) >> 8;
-                            let g = (p.0[1] as u32 * alpha) >> 8;
-                            let b = (p.0[2] as u32 * alpha) >> 8;
-                            buf[i] = alpha << 24 | r << 16 | g << 8 | b;
-                            i = i + 1;
-                        }
-                        draw_context.images.insert(path.clone(), (width, height, buf));
-                    }
-                }
+                let x = draw_context.eval_num(x)? * draw_context.scale;
+                let y = draw_context.eval_num(y)? * draw_context.scale;
+                let w = draw_context.eval_num(w)? * draw_context.scale;
+                let h = draw_context.eval_num(h)? * draw_context.scale;
+                let src_x = draw_context.eval_num(src_x)? * draw_context.scale;
+                let src_y = draw_context.eval_num(src_y)? * draw_context.scale;
 
-                let value = draw_context.images.get(path.as_str()).unwrap();
+                draw_context.load_image(&path)?;
+                draw_context.animated |= draw_context.animations.contains_key(path.as_str());
+
+                let value = draw_context.images.get(path.as_str())
+                    .ok_or_else(|| ErrorKind::SplashError(format!("image {} not found", path)))?;
+                // animated images advance their frames by the elapsed time
+                let data = match draw_context.animations.get(path.as_str()) {
+                    Some(animation) => animation.frame(draw_context.elapsed()),
+                    None => &value.2
+                };
                 let img = &Image {
                     width: value.0 as i32,
                     height: value.1 as i32,
-                    data: &value.2,
+                    data,
                 };
 
                 if w > 0.0 && h > 0.0 {
@@ -415,16 +1249,121 @@ impl Splash {
                     draw_context.draw_target.draw_image_at(x as f32, y as f32,img, &DrawOptions::default());
                 }
             }
+            "animation" => {
+                let path: String;
+                let x: String;
+                let y: String;
+                let w: String;
+                let h: String;
+                let frames: u64;
+                let interval: u64;
+                parse!(tokens, path, x, y, w, h, frames, interval);
+                let path = draw_context.eval_text(path);
+                let x = draw_context.eval_num(x)? * draw_context.scale;
+                let y = draw_context.eval_num(y)? * draw_context.scale;
+                let w = draw_context.eval_num(w)? * draw_context.scale;
+                let h = draw_context.eval_num(h)? * draw_context.scale;
+
+                // frames are placed next to each other in the image and get advanced by the elapsed time
+                let frame = if frames > 0 && interval > 0 {
+                    (draw_context.elapsed().as_millis() as u64 / interval) % frames
+                } else {
+                    0
+                };
+
+                draw_context.load_image(&path)?;
+                let value = draw_context.images.get(path.as_str())
+                    .ok_or_else(|| ErrorKind::SplashError(format!("image {} not found", path)))?;
+                let img = Image {
+                    width: value.0 as i32,
+                    height: value.1 as i32,
+                    data: &value.2,
+                };
+
+                let mut pb = PathBuilder::new();
+                pb.rect(x as f32, y as f32, w as f32, h as f32);
+                let ts = Transform::identity().then_translate(vec2((frame as f64 * w - x) as f32, -y as f32));
+                let source = Source::Image(img,
+                                           ExtendMode::Pad,
+                                           FilterMode::Nearest,
+                                           ts);
+                draw_context.draw_target.fill(&pb.finish(), &source, &DrawOptions::default());
+            }
             "textfont" => {
-                let mut path_buffer = draw_context.basedir.clone();
-                path_buffer.push(tokens[1].clone());
-                draw_context.text_font = Some(
-                    Font::from_path(path_buffer, 0).expect("failed to load font"),
-                );
+                if tokens.len() < 2 {
+                    bail!(ErrorKind::SplashError(format!("parameter 1 of command {} is missing", tokens[0])));
+                } else if tokens[1] == "family" && tokens.len() > 2 {
+                    // family names may contain whitespace and be quoted, a bundled font may be given as fallback
+                    let spec = tokens[2..].join(" ");
+                    let (family, fallback) = match spec.split_once(":") {
+                        Some((family, fallback)) => (String::from(family), Some(String::from(fallback))),
+                        None => (spec, None)
+                    };
+                    let family = family.trim().trim_matches('"');
+                    // generic families select the default system font of that kind
+                    let family_name = match family {
+                        "sans-serif" => FamilyName::SansSerif,
+                        "serif" => FamilyName::Serif,
+                        "monospace" => FamilyName::Monospace,
+                        _ => FamilyName::Title(String::from(family))
+                    };
+                    let font = SystemSource::new()
+                        .select_best_match(&[family_name], &Properties::new())
+                        .ok()
+                        .and_then(|handle| handle.load().ok());
+                    draw_context.text_font = match (font, fallback) {
+                        (Some(font), _) => Some(font),
+                        (None, Some(fallback)) => {
+                            let mut path_buffer = draw_context.basedir.clone();
+                            path_buffer.push(fallback.trim());
+                            Some(Font::from_path(&path_buffer, 0)
+                                .chain_err(|| ErrorKind::SplashError(format!("failed to load font {:?}", &path_buffer)))?)
+                        }
+                        (None, None) => bail!(ErrorKind::SplashError(format!("failed to find system font family {}", family)))
+                    };
+                } else {
+                    let mut path_buffer = draw_context.basedir.clone();
+                    path_buffer.push(tokens[1].clone());
+                    draw_context.text_font = Some(
+                        Font::from_path(&path_buffer, 0)
+                            .chain_err(|| ErrorKind::SplashError(format!("failed to load font {:?}", &path_buffer)))?,
+                    );
+                }
             }
             "textsize" => {
                 parse!(tokens, draw_context.text_size);
             }
+            "clip" => {
+                let x: String;
+                let y: String;
+                let w: String;
+                let h: String;
+                parse!(tokens, x, y, w, h);
+                let x = draw_context.eval_num(x)? * draw_context.scale;
+                let y = draw_context.eval_num(y)? * draw_context.scale;
+                let w = draw_context.eval_num(w)? * draw_context.scale;
+                let h = draw_context.eval_num(h)? * draw_context.scale;
+                let mut pb = PathBuilder::new();
+                pb.rect(x as f32, y as f32, w as f32, h as f32);
+                draw_context.draw_target.push_clip(&pb.finish());
+                draw_context.clips = draw_context.clips + 1;
+            }
+            "endclip" => {
+                if draw_context.clips == 0 {
+                    bail!(ErrorKind::SplashError(String::from("endclip without clip")));
+                }
+                draw_context.draw_target.pop_clip();
+                draw_context.clips = draw_context.clips - 1;
+            }
+            "textleading" => {
+                let leading: String;
+                parse!(tokens, leading);
+                draw_context.text_leading = if leading == "auto" {
+                    None
+                } else {
+                    Some(draw_context.eval_num(leading)? as f32)
+                };
+            }
             "textalign" => {
                 let align: String;
                 parse!(tokens, align);
@@ -437,59 +1376,298 @@ impl Splash {
                 }
             }
             "fill" => {
-                let r: u8;
-                let g: u8;
-                let b: u8;
-                parse!(tokens, r, g, b);
-                draw_context.fill = (r, g, b, 255);
+                if tokens.len() == 2 && tokens[1].starts_with("#") {
+                    draw_context.fill = Splash::parse_hex_color(&tokens[1]).ok_or_else(|| ErrorKind::SplashError(
+                        format!("parameter 1 of command {} is not a valid hex color", tokens[0])
+                    ))?;
+                } else {
+                    let r: String;
+                    let g: String;
+                    let b: String;
+                    let a: String;
+                    if tokens.len() == 5 {
+                        parse!(tokens, r, g, b, a);
+                    } else {
+                        parse!(tokens, r, g, b);
+                        a = String::from("255");
+                    }
+                    draw_context.fill = (draw_context.eval_color(r)?, draw_context.eval_color(g)?,
+                                         draw_context.eval_color(b)?, draw_context.eval_color(a)?);
+                }
             }
-            "filltext" => {
-                let source = Source::Solid(SolidSource {
-                    r: draw_context.fill.0,
-                    g: draw_context.fill.1,
-                    b: draw_context.fill.2,
-                    a: 255,
-                });
+            "gradient" => {
+                let x1: String;
+                let y1: String;
+                let r1: String;
+                let g1: String;
+                let b1: String;
+                let x2: String;
+                let y2: String;
+                let r2: String;
+                let g2: String;
+                let b2: String;
+                let x: String;
+                let y: String;
+                let w: String;
+                let h: String;
+                if tokens.len() == 15 {
+                    parse!(tokens, x1, y1, r1, g1, b1, x2, y2, r2, g2, b2, x, y, w, h);
+                } else {
+                    // without an explicit region the whole splash gets filled
+                    parse!(tokens, x1, y1, r1, g1, b1, x2, y2, r2, g2, b2);
+                    x = String::from("0");
+                    y = String::from("0");
+                    w = (draw_context.draw_target.width() as f64 / draw_context.scale).to_string();
+                    h = (draw_context.draw_target.height() as f64 / draw_context.scale).to_string();
+                }
+                let x1 = draw_context.eval_num(x1)? * draw_context.scale;
+                let y1 = draw_context.eval_num(y1)? * draw_context.scale;
+                let x2 = draw_context.eval_num(x2)? * draw_context.scale;
+                let y2 = draw_context.eval_num(y2)? * draw_context.scale;
+                let x = draw_context.eval_num(x)? * draw_context.scale;
+                let y = draw_context.eval_num(y)? * draw_context.scale;
+                let w = draw_context.eval_num(w)? * draw_context.scale;
+                let h = draw_context.eval_num(h)? * draw_context.scale;
+                let color1 = Color::new(255, draw_context.eval_color(r1)?, draw_context.eval_color(g1)?, draw_context.eval_color(b1)?);
+                let color2 = Color::new(255, draw_context.eval_color(r2)?, draw_context.eval_color(g2)?, draw_context.eval_color(b2)?);
 
+                let source = Source::new_linear_gradient(
+                    Gradient {
+                        stops: vec![
+                            GradientStop { position: 0.0, color: color1 },
+                            GradientStop { position: 1.0, color: color2 },
+                        ],
+                    },
+                    Point::new(x1 as f32, y1 as f32),
+                    Point::new(x2 as f32, y2 as f32),
+                    Spread::Pad,
+                );
+                // the opacity is taken from the fill color like for the other shapes
+                draw_context.draw_target.fill_rect(x as f32, y as f32, w as f32, h as f32, &source, &DrawOptions {
+                    alpha: draw_context.fill.3 as f32 / 255.0,
+                    ..DrawOptions::default()
+                });
+            }
+            "filltext" => {
                 let x: String;
                 let y: String;
                 parse!(tokens, x, y);
-                let x = draw_context.eval_num(x) * draw_context.scale;
-                let y = draw_context.eval_num(y) * draw_context.scale;
+                let x = draw_context.eval_num(x)? * draw_context.scale;
+                let y = draw_context.eval_num(y)? * draw_context.scale;
                 let text = draw_context.eval_text(tokens[3..].join(" "));
 
-                let pointsize = draw_context.text_size * draw_context.scale as f32;
-                let font = &draw_context.text_font.clone().unwrap();
-
-                let mut width = 0.0;
-                for c in text.chars() {
-                    let id = font.glyph_for_char(c).unwrap();
-                    width = width + font.advance(id).unwrap().x() as f32 * pointsize / 24. / 96.;
-                }
+                // line breaks can be given literally as \n or be contained in placeholder values
+                let lines: Vec<String> = text
+                    .replace("\\n", "\n")
+                    .split('\n')
+                    .map(|line| line.to_string())
+                    .collect();
+                draw_context.draw_lines(x as f32, y as f32, &lines)?;
+            }
+            "wraptext" => {
+                let x: String;
+                let y: String;
+                let max_width: String;
+                parse!(tokens, x, y, max_width);
+                let x = draw_context.eval_num(x)? * draw_context.scale;
+                let y = draw_context.eval_num(y)? * draw_context.scale;
+                let max_width = draw_context.eval_num(max_width)? * draw_context.scale;
+                let text = draw_context.eval_text(tokens[4..].join(" "));
 
-                draw_context.draw_target.draw_text(
-                    &draw_context.text_font
-                        .clone()
-                        .expect("text font must be given before text is drawn"),
-                    pointsize,
-                    text.as_str(),
-                    Point::new(x as f32 - width * draw_context.text_align, y as f32),
-                    &source,
-                    &DrawOptions {
-                        alpha: draw_context.fill.3 as f32 / 255.0,
-                        ..DrawOptions::default()
-                    },
-                );
+                let lines = draw_context.wrap_lines(&text.replace("\\n", "\n"), max_width as f32)?;
+                draw_context.draw_lines(x as f32, y as f32, &lines)?;
             }
             _ => {
 
             }
         }
-        return draw_context;
+        return Ok(draw_context);
+    }
+}
+
+impl AnimatedImage {
+    /// Returns the frame shown after the given time
+    fn frame(&self, elapsed: Duration) -> &Vec<u32> {
+        let (duration, last) = self.frames.last().unwrap();
+        let time = elapsed.as_millis() as u64 % duration;
+        return self.frames.iter()
+            .find(|(end, _)| time < *end)
+            .map(|(_, data)| data)
+            .unwrap_or(last);
     }
 }
 
 impl DrawContext {
+    const LINE_HEIGHT: f32 = 1.2;
+
+    fn new(scale: f64, basedir: PathBuf, placeholders: HashMap<String, String>, clock: Arc<dyn Clock>, width: usize, height: usize) -> DrawContext {
+        let start = clock.now();
+        return DrawContext {
+            scale,
+            fill: (0, 0, 0, 255),
+            text_font: None,
+            text_size: 12.0,
+            text_align: 0.0,
+            text_leading: None,
+            clips: 0,
+            basedir,
+            images: HashMap::new(),
+            animations: HashMap::new(),
+            animated: false,
+            placeholders,
+            clock,
+            start,
+
+            draw_target: DrawTarget::new(width as i32, height as i32)
+        };
+    }
+
+    /// Time since the splash screen is drawn, advances animations
+    fn elapsed(&self) -> Duration {
+        return self.clock.elapsed(self.start);
+    }
+
+    /// Loads and caches the image for the given path (alternatives are separated by colons)
+    fn load_image(&mut self, path: &String) -> Result<()> {
+        if !self.images.contains_key(path.as_str()) {
+            for alternative in path.split(":") {
+                let mut path_buffer = self.basedir.clone();
+                path_buffer.push(alternative);
+                if !path_buffer.exists() {
+                    continue;
+                }
+                if let Some((width, height, animation)) = DrawContext::load_animation(&path_buffer)? {
+                    self.images.insert(path.clone(), (width, height, animation.frames[0].1.clone()));
+                    self.animations.insert(path.clone(), animation);
+                    continue;
+                }
+                let img = image::open(&path_buffer)
+                    .chain_err(|| ErrorKind::SplashError(format!("could not load image {:?}", &path_buffer)))?;
+                let img = match img {
+                    DynamicImage::ImageRgba8(img) => img,
+                    img => img.to_rgba8()
+                };
+                let width = img.dimensions().0;
+                let height = img.dimensions().1;
+                self.images.insert(path.clone(), (width, height, DrawContext::to_argb(&img)));
+                self.animations.remove(path.as_str());
+            }
+        }
+        return Ok(());
+    }
+
+    /// Loads all frames of an animated PNG, returns `None` for still images
+    fn load_animation(path: &PathBuf) -> Result<Option<(u32, u32, AnimatedImage)>> {
+        let file = File::open(path)
+            .chain_err(|| ErrorKind::SplashError(format!("could not load image {:?}", path)))?;
+        let decoder = match PngDecoder::new(io::BufReader::new(file)) {
+            Ok(decoder) if decoder.is_apng().unwrap_or(false) => decoder,
+            _ => return Ok(None)
+        };
+        let (width, height) = decoder.dimensions();
+        let decoder = decoder.apng()
+            .chain_err(|| ErrorKind::SplashError(format!("could not load animated image {:?}", path)))?;
+        let frames = decoder.into_frames().collect_frames()
+            .chain_err(|| ErrorKind::SplashError(format!("could not load animated image {:?}", path)))?;
+        if frames.len() < 2 {
+            return Ok(None);
+        }
+        let mut end = 0;
+        let frames = frames.into_iter().map(|frame| {
+            let delay = Duration::from(frame.delay()).as_millis() as u64;
+            end = end + if delay > 0 { delay } else { DEFAULT_FRAME_DELAY };
+            return (end, DrawContext::to_argb(&frame.into_buffer()));
+        }).collect();
+        return Ok(Some((width, height, AnimatedImage { frames })));
+    }
+
+    fn to_argb(img: &RgbaImage) -> Vec<u32> {
+        return img.pixels().map(|p| DrawContext::premultiply(p.0)).collect();
+    }
+
+    /// Converts an RGBA pixel to the premultiplied ARGB format of raqote, rounding to the nearest value
+    fn premultiply(pixel: [u8; 4]) -> u32 {
+        let alpha = pixel[3] as u32;
+        let channel = |value: u8| (value as u32 * alpha + 127) / 255;
+        return alpha << 24 | channel(pixel[0]) << 16 | channel(pixel[1]) << 8 | channel(pixel[2]);
+    }
+
+    fn text_font(&self) -> Result<Font> {
+        return self.text_font
+            .clone()
+            .ok_or_else(|| ErrorKind::SplashError(String::from("text font must be given before text is drawn")).into());
+    }
+
+    fn text_width(&self, font: &Font, text: &str) -> Result<f32> {
+        let pointsize = self.text_size * self.scale as f32;
+        let mut width = 0.0;
+        for c in text.chars() {
+            let id = font.glyph_for_char(c)
+                .ok_or_else(|| ErrorKind::SplashError(format!("font does not contain character '{}'", c)))?;
+            let advance = font.advance(id)
+                .chain_err(|| ErrorKind::SplashError(format!("could not measure character '{}'", c)))?;
+            width = width + advance.x() as f32 * pointsize / 24. / 96.;
+        }
+        return Ok(width);
+    }
+
+    /// Breaks the given text into lines at whitespace so that each line fits into the given width.
+    /// Single words wider than the given width are put on a line of their own.
+    fn wrap_lines(&self, text: &str, max_width: f32) -> Result<Vec<String>> {
+        let font = self.text_font()?;
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let mut line = String::new();
+            for word in paragraph.split_whitespace() {
+                let candidate = if line.is_empty() { String::from(word) } else { format!("{} {}", line, word) };
+                if !line.is_empty() && self.text_width(&font, &candidate)? > max_width {
+                    lines.push(line);
+                    line = String::from(word);
+                } else {
+                    line = candidate;
+                }
+            }
+            lines.push(line);
+        }
+        return Ok(lines);
+    }
+
+    /// Draws the given lines below each other, starting with the baseline of the first line at the given position
+    /// Returns the distance between the baselines of two lines in pixels
+    fn line_advance(&self) -> f32 {
+        let leading = self.text_leading.unwrap_or(self.text_size * DrawContext::LINE_HEIGHT);
+        return leading * self.scale as f32;
+    }
+
+    fn draw_lines(&mut self, x: f32, y: f32, lines: &Vec<String>) -> Result<()> {
+        let source = Source::Solid(SolidSource {
+            r: self.fill.0,
+            g: self.fill.1,
+            b: self.fill.2,
+            a: 255,
+        });
+        let pointsize = self.text_size * self.scale as f32;
+        let font = self.text_font()?;
+
+        let mut y = y;
+        for line in lines {
+            let width = self.text_width(&font, line)?;
+            self.draw_target.draw_text(
+                &font,
+                pointsize,
+                line.as_str(),
+                Point::new(x - width * self.text_align, y),
+                &source,
+                &DrawOptions {
+                    alpha: self.fill.3 as f32 / 255.0,
+                    ..DrawOptions::default()
+                },
+            );
+            y = y + self.line_advance();
+        }
+        return Ok(());
+    }
+
     fn eval_text(&self, text: String) -> String {
         let mut text = text.clone();
         for (key, value) in &self.placeholders {
@@ -497,7 +1675,402 @@ impl DrawContext {
         }
         return text;
     }
-    fn eval_num(&self, text: String) -> f64 {
-        return meval::eval_str(self.eval_text(text)).unwrap();
+    fn eval_color(&self, text: String) -> Result<u8> {
+        return Ok(self.eval_num(text)?.round().clamp(0.0, 255.0) as u8);
+    }
+
+    fn eval_num(&self, text: String) -> Result<f64> {
+        let expression = self.eval_text(text);
+        return meval::eval_str(&expression)
+            .map_err(|e| ErrorKind::SplashError(format!("could not evaluate expression {}: {}", expression, e)).into());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use raqote::DrawTarget;
+    use crate::clock::{Clock, ManualClock, SystemClock};
+    use super::{AnimatedImage, BackgroundCache, DrawContext, Splash, APPEARANCE, APPEARANCE_TIMEOUT, DEFAULT_MAX_DPI, MIN_FRAME_RATE, SCREEN};
+
+    #[test]
+    fn test_malformed_command() {
+        let commands = vec![
+            vec![String::from("fill"), String::from("0"), String::from("0"), String::from("0")],
+            vec![String::from("textsize"), String::from("big")],
+        ];
+
+        let result = Splash::execute_commands(&commands, draw_context());
+
+        let error = result.err().expect("malformed command must fail");
+        assert_eq!("Error while showing splash screen: parameter 1 of command textsize is missing or has wrong type (in line 'textsize big')",
+                   error.to_string());
+    }
+
+    #[test]
+    fn test_invalid_expression() {
+        let commands = vec![
+            vec![String::from("gradient"), String::from("0+"), String::from("0"), String::from("0"), String::from("0"), String::from("0"),
+                 String::from("0"), String::from("10"), String::from("255"), String::from("255"), String::from("255")],
+        ];
+
+        assert_eq!(true, Splash::execute_commands(&commands, draw_context()).is_err());
+    }
+
+    #[test]
+    fn test_gradient_color_expression() {
+        let gradient: Vec<String> = "gradient 0 0 ${shade} ${shade}/2 0 10 0 255 255-${shade} 255"
+            .split(' ').map(String::from).collect();
+
+        let mut context = draw_context();
+        context.placeholders.insert(String::from("shade"), String::from("100"));
+        context.fill = (0, 0, 0, 128);
+        let context = Splash::execute_commands(&vec![gradient.clone()], context).unwrap();
+        // drawing the gradient does not change the fill color
+        assert_eq!((0, 0, 0, 128), context.fill);
+
+        // unknown placeholders are reported instead of drawing black
+        assert!(Splash::execute_commands(&vec![gradient], draw_context()).is_err());
+    }
+
+    #[test]
+    fn test_text_leading() {
+        let mut context = draw_context();
+        context.scale = 2.0;
+        context.text_size = 10.0;
+        assert_eq!(24.0, context.line_advance());
+
+        let commands = vec![vec![String::from("textleading"), String::from("8+${gap}")]];
+        context.placeholders.insert(String::from("gap"), String::from("7"));
+        context = Splash::execute_commands(&commands, context).unwrap();
+        assert_eq!(Some(15.0), context.text_leading);
+        assert_eq!(30.0, context.line_advance());
+
+        let commands = vec![vec![String::from("textleading"), String::from("auto")]];
+        context = Splash::execute_commands(&commands, context).unwrap();
+        assert_eq!(24.0, context.line_advance());
+    }
+
+    #[test]
+    fn test_clip() {
+        let clip = vec![String::from("clip"), String::from("10"), String::from("10"), String::from("100"), String::from("20")];
+        let end_clip = vec![String::from("endclip")];
+
+        let context = Splash::execute_commands(&vec![clip.clone(), clip.clone(), end_clip.clone()], draw_context()).unwrap();
+        // unmatched clips end with the commands
+        assert_eq!(0, context.clips);
+
+        assert!(Splash::execute_commands(&vec![clip.clone(), end_clip.clone(), end_clip.clone()], draw_context()).is_err());
+        assert!(Splash::execute_commands(&vec![vec![String::from("clip"), String::from("10")]], draw_context()).is_err());
+    }
+
+    fn draw_context() -> DrawContext {
+        return DrawContext {
+            scale: 1.0,
+            fill: (0, 0, 0, 255),
+            text_font: None,
+            text_size: 12.0,
+            text_align: 0.0,
+            text_leading: None,
+            clips: 0,
+            basedir: PathBuf::new(),
+            images: HashMap::new(),
+            animations: HashMap::new(),
+            animated: false,
+            placeholders: HashMap::new(),
+            clock: Arc::new(SystemClock),
+            start: Instant::now(),
+            draw_target: DrawTarget::new(10, 10)
+        };
+    }
+
+    #[test]
+    fn test_parse_splash_frame_rate() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        std::fs::write(path.join("splash"), "splash 500 300\n\n[background]\nfill 0 0 0\n").unwrap();
+        assert_eq!(None, Splash::parse_splash(&path).unwrap().frame_rate);
+        assert!(!Splash::parse_splash(&path).unwrap().is_animated());
+
+        std::fs::write(path.join("splash"), "splash 500 300\n\n[progress]\nimage spinner.png 10+5*sin(${elapsed}) 10\n").unwrap();
+        assert!(Splash::parse_splash(&path).unwrap().is_animated());
+
+        std::fs::write(path.join("splash"), "splash 500 300\nframerate 24\n").unwrap();
+        assert_eq!(Some(24), Splash::parse_splash(&path).unwrap().frame_rate);
+
+        std::fs::write(path.join("splash"), "splash 500 300\nframerate 1\n").unwrap();
+        assert_eq!(Some(MIN_FRAME_RATE), Splash::parse_splash(&path).unwrap().frame_rate);
+
+        std::fs::write(path.join("splash"), "splash 500 300\nframerate fast\n").unwrap();
+        assert!(Splash::parse_splash(&path).is_err());
+    }
+
+    #[test]
+    fn test_parse_splash_without_size() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        assert!(Splash::parse_splash(&path).is_err());
+
+        std::fs::write(path.join("splash"), "[background]\nfill 0 0 0\n").unwrap();
+        let error = Splash::parse_splash(&path).err().unwrap();
+        assert!(error.to_string().contains("size declaration"));
+
+        std::fs::write(path.join("splash"), "splash 500 0\n").unwrap();
+        assert!(Splash::parse_splash(&path).is_err());
+    }
+
+    #[test]
+    fn test_builtin_splash() {
+        let mut splash = Splash::builtin_splash();
+        assert_eq!((360, 140), (splash.width, splash.height));
+        assert!(splash.is_animated());
+        assert!(splash.background.iter().any(|tokens| tokens.join(" ").contains("${name}")));
+
+        let background = splash.background.clone();
+        splash.use_high_contrast();
+        assert_ne!(background, splash.background);
+        assert!(!splash.progress.is_empty());
+    }
+
+    #[test]
+    fn test_retry_dialog() {
+        let mut dialog = Splash::retry_dialog();
+        assert_eq!((420, 180), (dialog.width, dialog.height));
+        assert!(!dialog.is_animated());
+        assert_eq!(Some(vec![String::from("name"), String::from("message"), String::from("hint")]),
+                   Splash::referenced_placeholders(&dialog.background));
+
+        let background = dialog.background.clone();
+        dialog.use_high_contrast();
+        assert_ne!(background, dialog.background);
+    }
+
+    #[test]
+    fn test_screen_probed_once() {
+        // a second probe would create another winit event loop, which panics
+        let screen = Splash::get_screen_size();
+        assert_eq!(Some(&screen), SCREEN.get());
+        assert_eq!(screen, Splash::get_screen_size());
+    }
+
+    #[test]
+    fn test_appearance_probed_once() {
+        let start = Instant::now();
+        Splash::probe_appearance();
+        let appearance = Splash::get_appearance();
+        assert!(start.elapsed() < APPEARANCE_TIMEOUT + Duration::from_secs(1));
+        assert_eq!(Some(&appearance), APPEARANCE.get());
+        // the retry dialog uses the stored appearance instead of detecting it again
+        Splash::probe_appearance();
+        assert_eq!(appearance, Splash::get_appearance());
+    }
+
+    #[test]
+    fn test_parse_splash_line_endings() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        std::fs::write(path.join("splash"), "# logo and version\r\nsplash 500 300\r\n\r\n [background] \r\n  fill #FF0000\r\n# version\r\nfilltext 10 10 ${version}\r\n\t\r\n[progress]\r\nfill 0 0 0\r\n").unwrap();
+        let splash = Splash::parse_splash(&path).unwrap();
+        assert_eq!((500, 300), (splash.width, splash.height));
+        assert_eq!(vec![vec!["fill", "#FF0000"], vec!["filltext", "10", "10", "${version}"]], splash.background);
+        assert_eq!(vec![vec!["fill", "0", "0", "0"]], splash.progress);
+    }
+
+    #[test]
+    fn test_high_contrast() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        std::fs::write(path.join("splash"), "splash 500 300\n[background]\nfill 255 0 0\n[progress]\nfill 0 255 0\n[background.contrast]\nfill 0 0 0\n").unwrap();
+        let mut splash = Splash::parse_splash(&path).unwrap();
+        assert_eq!(vec![vec!["fill", "255", "0", "0"]], splash.background);
+        assert_eq!(vec![vec!["fill", "0", "0", "0"]], splash.contrast_background);
+
+        splash.use_high_contrast();
+        assert_eq!(vec![vec!["fill", "0", "0", "0"]], splash.background);
+        // the progress has no alternative
+        assert_eq!(vec![vec!["fill", "0", "255", "0"]], splash.progress);
+    }
+
+    #[test]
+    fn test_fade() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        std::fs::write(path.join("splash"), "splash 500 300\n").unwrap();
+        assert_eq!(None, Splash::parse_splash(&path).unwrap().fade);
+        std::fs::write(path.join("splash"), "splash 500 300\nfade 200 400\n").unwrap();
+        assert_eq!(Some((200, 400)), Splash::parse_splash(&path).unwrap().fade);
+
+        assert_eq!(0.0, Splash::fade_alpha((200, 400), Duration::from_millis(0), None));
+        assert_eq!(0.5, Splash::fade_alpha((200, 400), Duration::from_millis(100), None));
+        assert_eq!(1.0, Splash::fade_alpha((200, 400), Duration::from_millis(5000), None));
+        assert_eq!(0.75, Splash::fade_alpha((200, 400), Duration::from_millis(5000), Some(Duration::from_millis(100))));
+        assert_eq!(0.0, Splash::fade_alpha((200, 400), Duration::from_millis(5000), Some(Duration::from_millis(400))));
+        assert_eq!(1.0, Splash::fade_alpha((0, 0), Duration::from_millis(0), None));
+
+        assert_eq!(0x80804020, Splash::fade_pixel(0xFFFF8040, 0.5));
+        assert_eq!(0xFFFF8040, Splash::fade_pixel(0xFFFF8040, 1.0));
+        assert_eq!(0, Splash::fade_pixel(0xFFFF8040, 0.0));
+    }
+
+    #[test]
+    fn test_referenced_placeholders() {
+        let commands = vec![
+            vec![String::from("fill"), String::from("255*${dark}"), String::from("0"), String::from("${dark}")],
+            vec![String::from("filltext"), String::from("10"), String::from("10"), String::from("${version}${status}")],
+        ];
+        assert_eq!(Some(vec![String::from("dark"), String::from("version"), String::from("status")]), Splash::referenced_placeholders(&commands));
+
+        let commands = vec![
+            vec![String::from("animation"), String::from("a.png"), String::from("0"), String::from("0"), String::from("8"), String::from("8"), String::from("4"), String::from("100")],
+        ];
+        assert_eq!(None, Splash::referenced_placeholders(&commands));
+    }
+
+    #[test]
+    fn test_draw_background_cached() {
+        let commands = vec![vec![String::from("fill"), String::from("${dark}"), String::from("0"), String::from("0")]];
+        let placeholders = Splash::referenced_placeholders(&commands);
+        let mut cache: Option<BackgroundCache> = None;
+        let mut context = draw_context();
+        context.placeholders.insert(String::from("dark"), String::from("0"));
+        context.placeholders.insert(String::from("progress"), String::from("0.5"));
+
+        context = Splash::draw_background(&commands, &placeholders, &mut cache, context).unwrap();
+        assert_eq!((0, 0, 0, 255), context.fill);
+
+        // unrelated placeholders and progress drawing do not invalidate the background
+        context.placeholders.insert(String::from("progress"), String::from("0.6"));
+        context.fill = (9, 9, 9, 9);
+        context.draw_target.get_data_mut()[0] = 0xFFFFFFFF;
+        context = Splash::draw_background(&commands, &placeholders, &mut cache, context).unwrap();
+        assert_eq!((0, 0, 0, 255), context.fill);
+        assert_eq!(0, context.draw_target.get_data()[0]);
+
+        context.placeholders.insert(String::from("dark"), String::from("1"));
+        context = Splash::draw_background(&commands, &placeholders, &mut cache, context).unwrap();
+        assert_eq!((1, 0, 0, 255), context.fill);
+    }
+
+    #[test]
+    fn test_status_labels() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().to_path_buf();
+        File::create(path.join("strings.toml")).unwrap()
+            .write_all(b"[de]\ndownloading = \"Herunterladen\"\nstarting = \"Starten\"\n\n[de-CH]\nstarting = \"Starte\"\n").unwrap();
+
+        let labels = Splash::load_status_labels(Some(&path), "de-CH");
+        assert_eq!("Herunterladen", labels.get("downloading").unwrap());
+        assert_eq!("Starte", labels.get("starting").unwrap());
+
+        let labels = Splash::load_status_labels(Some(&path), "fr-FR");
+        assert_eq!("Downloading", labels.get("downloading").unwrap());
+        assert_eq!("Starting", labels.get("starting").unwrap());
+        assert_eq!("Running offline, update pending", labels.get("offline").unwrap());
+    }
+
+    #[test]
+    fn test_map_scale() {
+        assert_eq!((1.0, String::from("mdpi")), Splash::map_scale(1.0, DEFAULT_MAX_DPI));
+        assert_eq!((1.5, String::from("hdpi")), Splash::map_scale(1.25, DEFAULT_MAX_DPI));
+        assert_eq!((2.0, String::from("xhdpi")), Splash::map_scale(1.75, DEFAULT_MAX_DPI));
+        // the default buckets end with xhdpi
+        assert_eq!((2.0, String::from("xhdpi")), Splash::map_scale(3.0, DEFAULT_MAX_DPI));
+        assert_eq!((3.0, String::from("xxhdpi")), Splash::map_scale(3.0, "xxxhdpi"));
+        assert_eq!((4.0, String::from("xxxhdpi")), Splash::map_scale(4.0, "xxxhdpi"));
+        assert_eq!((2.0, String::from("xhdpi")), Splash::map_scale(2.25, "xxxhdpi"));
+        assert_eq!((1.0, String::from("mdpi")), Splash::map_scale(2.0, "mdpi"));
+    }
+
+    #[test]
+    fn test_premultiply() {
+        // opaque pixels keep their color (no darkening by the alpha)
+        assert_eq!(0xFFFFFFFF, DrawContext::premultiply([255, 255, 255, 255]));
+        assert_eq!(0xFF102030, DrawContext::premultiply([0x10, 0x20, 0x30, 255]));
+        // anti-aliased edge of a white logo
+        assert_eq!(0x80808080, DrawContext::premultiply([255, 255, 255, 128]));
+        assert_eq!(0x01010101, DrawContext::premultiply([255, 255, 255, 1]));
+        assert_eq!(0x40201008, DrawContext::premultiply([128, 64, 32, 64]));
+        assert_eq!(0x00000000, DrawContext::premultiply([255, 128, 0, 0]));
+    }
+
+    #[test]
+    fn test_load_image_premultiplied() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let pixels: Vec<u8> = vec![255, 255, 255, 255, 255, 255, 255, 128, 0, 0, 0, 128, 200, 100, 50, 0];
+        image::RgbaImage::from_raw(2, 2, pixels).unwrap().save(temp_dir.path().join("logo.png")).unwrap();
+
+        let mut context = draw_context();
+        context.basedir = temp_dir.path().to_path_buf();
+        context.load_image(&String::from("logo.png")).unwrap();
+        let (width, height, buffer) = context.images.get("logo.png").unwrap();
+        assert_eq!((2, 2), (*width, *height));
+        assert_eq!(&vec![0xFFFFFFFF, 0x80808080, 0x80000000, 0x00000000], buffer);
+        assert!(context.animations.is_empty());
+        assert!(DrawContext::load_animation(&temp_dir.path().join("logo.png")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_animated_image_frame() {
+        let animation = AnimatedImage { frames: vec![(100, vec![1]), (150, vec![2]), (300, vec![3])] };
+        assert_eq!(&vec![1], animation.frame(Duration::from_millis(0)));
+        assert_eq!(&vec![1], animation.frame(Duration::from_millis(99)));
+        assert_eq!(&vec![2], animation.frame(Duration::from_millis(100)));
+        assert_eq!(&vec![3], animation.frame(Duration::from_millis(299)));
+        // the animation loops
+        assert_eq!(&vec![1], animation.frame(Duration::from_millis(300)));
+        assert_eq!(&vec![2], animation.frame(Duration::from_millis(1320)));
+    }
+
+    #[test]
+    fn test_animation_clock() {
+        let clock = Arc::new(ManualClock::new());
+        let mut context = draw_context();
+        context.clock = clock.clone();
+        context.start = clock.now();
+        let animation = AnimatedImage { frames: vec![(100, vec![1]), (200, vec![2])] };
+        assert_eq!(&vec![1], animation.frame(context.elapsed()));
+
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(&vec![2], animation.frame(context.elapsed()));
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(&vec![1], animation.frame(context.elapsed()));
+    }
+
+    #[test]
+    fn test_position() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("splash.position");
+        assert_eq!(None, Splash::read_position(&path));
+
+        Splash::write_position(&path, -1200, 300);
+        assert_eq!(Some((-1200, 300)), Splash::read_position(&path));
+
+        let monitors = vec![(0, 0, 1920, 1080), (-1280, 0, 1280, 1024)];
+        assert_eq!(true, Splash::is_on_monitor(-1200, 300, &monitors));
+        assert_eq!(true, Splash::is_on_monitor(100, 100, &monitors));
+        assert_eq!(false, Splash::is_on_monitor(2000, 100, &monitors));
+        assert_eq!(false, Splash::is_on_monitor(-1200, 1050, &monitors));
+    }
+
+    #[test]
+    fn test_format_speed() {
+        assert_eq!((String::new(), String::new()), Splash::format_speed(0, 1000));
+        assert_eq!((String::from("2.0 MB/s"), String::from("30s")), Splash::format_speed(2 * 1024 * 1024, 60 * 1024 * 1024));
+        assert_eq!((String::from("1.0 MB/s"), String::from("2m 5s")), Splash::format_speed(1024 * 1024, 125 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_parse_hex_color() {
+        assert_eq!(Some((0x1a, 0x2b, 0x3c, 255)), Splash::parse_hex_color("#1A2B3C"));
+        assert_eq!(Some((0x1a, 0x2b, 0x3c, 0x80)), Splash::parse_hex_color("#1a2b3c80"));
+        assert_eq!(None, Splash::parse_hex_color("#1A2B3"));
+        assert_eq!(None, Splash::parse_hex_color("#GGGGGG"));
+        assert_eq!(None, Splash::parse_hex_color("1A2B3C"));
     }
 }